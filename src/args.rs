@@ -1,41 +1,347 @@
+use crate::config::Input;
+use crate::digest::{Algorithm, ChecksumFormat};
+use crate::error::OnError;
+use crate::filter;
+use crate::fs::SymlinkMode;
+use crate::manifest::{ManifestFormat, ManifestLocation};
 use crate::wrapper;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
+/// Zip a directory with respect to .zipignore
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Parser, Clone)]
-// #[clap(infer_subcommands(true))]
+#[clap(args_conflicts_with_subcommands(true))]
 pub struct CLI {
-    /// Target Directory to be zipped, current directory by default
-    #[clap(long, short, value_parser, required(false))]
-    pub input: Option<PathBuf>,
-    /// Output file name, {parent_directory}.zip by default
-    #[clap(long, short, value_parser, required(false))]
-    pub output: Option<PathBuf>,
-    /// Max depth in the tree view
-    #[clap(long, short, value_parser)]
-    pub depth: Option<usize>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(flatten)]
+    pub zip: ZipArgs,
     /// Health Check
-    #[clap(long, action = clap::ArgAction::Count)]
+    #[clap(long, global = true, action = clap::ArgAction::Count)]
     health_check: u8,
     /// Turn debugging information on
-    #[clap(long, action = clap::ArgAction::Count)]
+    #[clap(long, global = true, action = clap::ArgAction::Count)]
     debug: u8,
+}
+
+impl CLI {
+    /// The command to run, `zip` if no subcommand is given
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Zip(self.zip))
+    }
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Zip the target directory (default)
+    Zip(ZipArgs),
+    /// Only show the tree of files that would be zipped
+    Tree(TreeArgs),
+    /// Verify the integrity of an existing archive
+    Verify(VerifyArgs),
+    /// List the entries of an existing archive
+    List(ListArgs),
+    /// Extract an existing archive
+    Extract(ExtractArgs),
+    /// Tell which rule includes or leaves out a path
+    Explain(ExplainArgs),
+}
+
+// What goes into the archive
+#[derive(Args, Clone)]
+pub struct InputArgs {
+    /// Directory or file to be zipped, `path=prefix` puts it under prefix in
+    /// the archive. Repeatable, current directory by default
+    #[clap(long, short, value_parser = Input::parse, required(false))]
+    pub input: Vec<Input>,
+    /// Add the paths listed in a file, `-` for stdin, one per line or NUL separated
+    #[clap(long, value_parser, required(false))]
+    pub files_from: Option<PathBuf>,
+}
+
+// Where the configuration files are read from
+#[derive(Args, Clone)]
+pub struct ConfigArgs {
+    /// Read this file instead of the nearest lasagna.toml or .lasagnarc
+    #[clap(long, value_parser, required(false), conflicts_with = "no_config")]
+    pub config: Option<PathBuf>,
+    /// Ignore all configuration files
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value = "false")]
+    pub no_config: bool,
+    /// Apply a named profile of the configuration file, `[profile.<name>]`
+    #[clap(long, value_parser, required(false), conflicts_with = "no_config")]
+    pub profile: Option<String>,
+}
+
+// Options controlling which files are picked up by the walker
+#[derive(Args, Clone)]
+pub struct WalkArgs {
+    /// Max depth in the tree view
+    #[clap(long, short, value_parser)]
+    pub depth: Option<usize>,
+    /// Ignore hidden files
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_ignore_hidden"
+    )]
+    pub ignore_hidden: Option<bool>,
+    /// Keep hidden files, even if the configuration ignores them
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "ignore_hidden"
+    )]
+    pub no_ignore_hidden: Option<bool>,
+    /// Read gitignore as part of .zipignore
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_read_gitignore"
+    )]
+    pub read_gitignore: Option<bool>,
+    /// Don't read gitignore, even if the configuration does
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "read_gitignore"
+    )]
+    pub no_read_gitignore: Option<bool>,
+    /// Only keep files matching this glob, in gitignore syntax. Repeatable,
+    /// takes precedence over the ignore files
+    #[clap(long, value_parser, required(false))]
+    pub include: Vec<String>,
+    /// Leave out paths matching this glob, in gitignore syntax. Repeatable,
+    /// takes precedence over the ignore files and --include
+    #[clap(long, value_parser, required(false))]
+    pub exclude: Vec<String>,
+    /// Leave out files larger than this, e.g. 50M
+    #[clap(long, value_parser = filter::parse_size, required(false))]
+    pub max_file_size: Option<u64>,
+    /// Only keep files modified after a date or within an age, e.g. 2026-01-01 or 30d
+    #[clap(long, value_parser = filter::parse_time, required(false))]
+    pub newer_than: Option<SystemTime>,
+    /// Only keep files modified before a date or longer ago than an age, e.g. 30d
+    #[clap(long, value_parser = filter::parse_time, required(false))]
+    pub older_than: Option<SystemTime>,
+    /// Only keep files of these types, e.g. rust,md (see ripgrep --type-list)
+    #[clap(long = "type", value_parser, value_delimiter = ',', required(false))]
+    pub types: Vec<String>,
+    /// What to do with symbolic links, follow by default
+    #[clap(long, value_enum, required(false))]
+    pub symlinks: Option<SymlinkMode>,
+}
+
+#[derive(Args, Clone)]
+pub struct ZipArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    /// Output file name, {parent_directory}.zip by default
+    #[clap(long, short, value_parser, required(false))]
+    pub output: Option<PathBuf>,
+    #[clap(flatten)]
+    pub walk: WalkArgs,
+    #[clap(flatten)]
+    pub config: ConfigArgs,
     /// Dry Run
     #[clap(long, action = clap::ArgAction::SetTrue)]
     pub dry_run: bool,
-    /// Ignore hidden files
-    #[clap(long, action = clap::ArgAction::SetTrue, default_value = "false")]
-    pub ignore_hidden: bool,
-    /// Read gitignore as part of .zipignore
-    #[clap(long, action = clap::ArgAction::SetTrue, default_value = "false")]
-    pub read_gitignore: bool,
+    /// Also show what is left out in the tree, marked with what left it out
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub show_ignored: bool,
     /// Wrap all the files with output file name, parent file name by default
-    #[clap(long, action = clap::ArgAction::SetTrue, default_value = "false")]
-    pub parent: bool,
-    /// Indicate the compression method
-    #[clap(long, value_enum, required(false), default_value = "deflated")]
-    pub compression_method: wrapper::CompressionMethod,
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_parent"
+    )]
+    pub parent: Option<bool>,
+    /// Don't wrap the files, even if the configuration does
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "parent"
+    )]
+    pub no_parent: Option<bool>,
+    /// Indicate the compression method, deflated by default
+    #[clap(long, value_enum, required(false))]
+    pub compression_method: Option<wrapper::CompressionMethod>,
     /// Indicate the compression level
     #[clap(long, value_parser, required(false))]
     pub compression_level: Option<i64>,
+    /// Digests of the archive to be printed, md5,sha256 by default
+    #[clap(long, value_enum, value_delimiter = ',', required(false))]
+    pub digest: Option<Vec<Algorithm>>,
+    /// Write checksum sidecars next to the archive, e.g. `archive.zip.sha256`
+    #[clap(long, value_enum, value_delimiter = ',', required(false))]
+    pub sidecar: Vec<Algorithm>,
+    /// Format of the checksum sidecars, gnu by default
+    #[clap(long, value_enum, required(false))]
+    pub sidecar_format: Option<ChecksumFormat>,
+    /// Emit a manifest with the SHA256 of every file
+    #[clap(long, value_enum, required(false))]
+    pub manifest: Option<ManifestFormat>,
+    /// Where the manifest goes, embed by default
+    #[clap(long, value_enum, required(false))]
+    pub manifest_location: Option<ManifestLocation>,
+    /// Produce a bit-for-bit reproducible archive: sorted entries, mtimes
+    /// clamped to $SOURCE_DATE_EPOCH (1980-01-01 if unset) and normalised permissions
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_reproducible"
+    )]
+    pub reproducible: Option<bool>,
+    /// Keep the actual mtimes and permissions, even if the configuration doesn't
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "reproducible"
+    )]
+    pub no_reproducible: Option<bool>,
+    /// What to do with a file which can't be opened or read, abort by default, and
+    /// with a directory which can't be walked or a link loop, warn by default
+    #[clap(long, value_enum, required(false))]
+    pub on_error: Option<OnError>,
+    /// What to do with a FIFO, socket or device, which can't be zipped, warn by default
+    #[clap(long, value_enum, required(false))]
+    pub on_special_file: Option<OnError>,
+    /// Number of files compressed at the same time, and of threads walking the inputs,
+    /// the number of CPUs by default
+    #[clap(long, short, value_parser, required(false))]
+    pub jobs: Option<NonZeroUsize>,
+    /// Report the compression ratio overall, of the largest files and by extension
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_stats"
+    )]
+    pub stats: Option<bool>,
+    /// Don't report the compression ratio, even if the configuration does
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "stats"
+    )]
+    pub no_stats: Option<bool>,
+    /// Print nothing but warnings and errors: no tree, progress or digests
+    #[clap(
+        long,
+        short,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_quiet"
+    )]
+    pub quiet: Option<bool>,
+    /// Print the tree, progress and digests, even if the configuration is quiet
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "quiet"
+    )]
+    pub no_quiet: Option<bool>,
+}
+
+#[derive(Args, Clone)]
+pub struct TreeArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(flatten)]
+    pub walk: WalkArgs,
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// Also show what is left out, marked with what left it out
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub show_ignored: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct ExplainArgs {
+    /// Paths to explain, inside one of the inputs
+    #[clap(value_parser, required(true))]
+    pub paths: Vec<PathBuf>,
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(flatten)]
+    pub walk: WalkArgs,
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct VerifyArgs {
+    /// Archive to be verified
+    #[clap(value_parser)]
+    pub archive: PathBuf,
+    /// Source to compare the archive against, as given to `--input` when zipping.
+    /// Repeatable
+    #[clap(long, short, value_parser = Input::parse, required(false))]
+    pub source: Vec<Input>,
+    #[clap(flatten)]
+    pub walk: WalkArgs,
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// The archive was created with `--parent`
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_parent"
+    )]
+    pub parent: Option<bool>,
+    /// The archive was created with `--no-parent`
+    #[clap(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "parent"
+    )]
+    pub no_parent: Option<bool>,
+    /// Compare the SHA256 of the contents as well
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value = "false")]
+    pub hash: bool,
+    /// Digests of the archive to be printed, md5,sha256 by default
+    #[clap(long, value_enum, value_delimiter = ',', required(false))]
+    pub digest: Option<Vec<Algorithm>>,
+    /// Check the archive against a checksum sidecar in GNU or BSD format
+    #[clap(long, short, value_parser, required(false))]
+    pub checksum: Option<PathBuf>,
+}
+
+#[derive(Args, Clone)]
+pub struct ListArgs {
+    /// Archive to be listed
+    #[clap(value_parser)]
+    pub archive: PathBuf,
+    /// Show sizes, compression method and modification time of each entry
+    #[clap(long, short, action = clap::ArgAction::SetTrue)]
+    pub long: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct ExtractArgs {
+    /// Archive to be extracted
+    #[clap(value_parser)]
+    pub archive: PathBuf,
+    /// Target directory, current directory by default
+    #[clap(long, short, value_parser, required(false))]
+    pub output: Option<PathBuf>,
+    /// Restore the setuid, setgid and sticky bits, which are dropped by default. Only
+    /// archives made by other tools hold them, lasagna never stores them
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value = "false")]
+    pub keep_setuid: bool,
 }
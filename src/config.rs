@@ -1,10 +1,124 @@
-use crate::args::CLI;
-use crate::{args, parent_dir_name};
-use std::path::PathBuf;
+use crate::args::{ConfigArgs, ExplainArgs, InputArgs, TreeArgs, VerifyArgs, WalkArgs, ZipArgs};
+use crate::config_file::ConfigFile;
+use crate::digest::{Algorithm, ChecksumFormat};
+use crate::error::{Context, Error, OnError, Result};
+use crate::extract::enclosed_path;
+use crate::filter;
+use crate::fs::SymlinkMode;
+use crate::manifest::{ManifestFormat, ManifestLocation};
+use crate::parent_dir_name;
+use serde::Deserialize;
+use std::io::Read;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A directory or a file to be archived
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Input {
+    pub path: PathBuf,
+    /// Directory of the archive the input goes into, the root if empty
+    pub prefix: PathBuf,
+}
+
+impl Input {
+    pub fn new<T: Into<PathBuf>>(path: T) -> Input {
+        Input {
+            path: path.into(),
+            prefix: PathBuf::new(),
+        }
+    }
+
+    /// `path` or `path=prefix`, an existing path containing `=` is taken as is
+    pub fn parse(value: &str) -> std::result::Result<Input, String> {
+        match value.rsplit_once('=') {
+            Some((path, prefix)) if !Path::new(value).exists() => Ok(Input {
+                path: PathBuf::from(path),
+                prefix: match prefix {
+                    "" => PathBuf::new(),
+                    _ => enclosed_path(prefix).ok_or(format!("invalid prefix {}", prefix))?,
+                },
+            }),
+            _ => Ok(Input::new(value)),
+        }
+    }
+
+    /// Prefix the path with `base`, for the inputs of a configuration file
+    pub fn relative_to(self, base: &Path) -> Input {
+        Input {
+            path: base.join(self.path),
+            ..self
+        }
+    }
+
+    /// A path of a file list, stored in the archive under the same path
+    fn listed(path: &str) -> Result<Input> {
+        let relative = path.trim_start_matches('/');
+        let name = enclosed_path(relative)
+            .ok_or_else(|| Error::input(format!("{} can't be stored in an archive", path)))?;
+        let prefix = if Path::new(path).is_dir() {
+            name
+        } else {
+            name.parent().map(Path::to_path_buf).unwrap_or_default()
+        };
+        Ok(Input {
+            path: PathBuf::from(path),
+            prefix,
+        })
+    }
+}
+
+impl TryFrom<String> for Input {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        Input::parse(&value)
+    }
+}
+
+/// Read a list of paths separated by newlines, or by NUL if there is any.
+/// `-` stands for stdin.
+fn files_from(list: &Path) -> Result<Vec<Input>> {
+    let mut content = String::new();
+    if list == Path::new("-") {
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .with_path(list)?;
+    } else {
+        content = std::fs::read_to_string(list).with_path(list)?;
+    }
+    let separator = if content.contains('\0') { '\0' } else { '\n' };
+    content
+        .split(separator)
+        .map(|x| x.strip_suffix('\r').unwrap_or(x))
+        .filter(|x| !x.is_empty())
+        .map(Input::listed)
+        .collect()
+}
+
+/// The inputs of the command line, or those of the configuration if none is given
+fn inputs(args: InputArgs, layered: Vec<Input>) -> Result<Vec<Input>> {
+    let mut inputs = args.input;
+    if let Some(list) = &args.files_from {
+        inputs.extend(files_from(list)?);
+    } else if inputs.is_empty() {
+        inputs = layered;
+    }
+    for input in &inputs {
+        if !input.path.exists() {
+            return Err(Error::input(format!(
+                "{} does not exist",
+                input.path.display()
+            )));
+        }
+    }
+    Ok(inputs)
+}
 
 #[derive(Debug)]
 pub struct Config {
-    pub input: PathBuf,
+    pub inputs: Vec<Input>,
     pub output: PathBuf,
     pub depth: Option<usize>,
     pub ignore_hidden: bool,
@@ -12,21 +126,368 @@ pub struct Config {
     pub parent: bool,
     pub compression_method: zip::CompressionMethod,
     pub compression_level: Option<i64>,
+    pub digests: Vec<Algorithm>,
+    pub sidecars: Vec<Algorithm>,
+    pub sidecar_format: ChecksumFormat,
+    pub manifest: Option<ManifestFormat>,
+    pub manifest_location: ManifestLocation,
+    pub reproducible: bool,
+    /// Upper bound of the timestamps in reproducible mode
+    pub source_date_epoch: Option<i64>,
+    pub on_error: OnError,
+    /// What to do with an entry the walk can't read, e.g. a directory without
+    /// permission or a link loop, `on_error` once it is set
+    pub on_walk_error: OnError,
+    /// What to do with FIFOs, sockets and devices, which can't be zipped
+    pub on_special_file: OnError,
+    /// Number of files compressed at the same time, and of threads walking the inputs
+    pub jobs: NonZeroUsize,
+    /// Report how well the files compressed
+    pub stats: bool,
+    /// Nothing is printed but warnings and errors
+    pub quiet: bool,
+    /// Files larger than this are left out
+    pub max_file_size: Option<u64>,
+    pub newer_than: Option<SystemTime>,
+    pub older_than: Option<SystemTime>,
+    /// Names of the file types to restrict the walk to, e.g. `rust`
+    pub types: Vec<String>,
+    /// Patterns in .gitignore syntax to restrict the walk to
+    pub includes: Vec<String>,
+    /// Patterns in .gitignore syntax to leave out of the walk
+    pub excludes: Vec<String>,
+    pub symlinks: SymlinkMode,
+}
+
+impl Config {
+    /// Name of the entry in the archive for its path relative to the archive root
+    pub fn entry_name(&self, relative: &Path) -> Result<String> {
+        let relative = utf8(relative)?;
+        if self.parent {
+            let stem = self.output.file_stem().unwrap_or_default();
+            Ok(format!("{}/{}", utf8(Path::new(stem))?, relative))
+        } else {
+            Ok(relative.to_string())
+        }
+    }
+
+    /// Path of a file written next to the output, e.g. `archive.zip.SHA256SUMS`
+    /// for the manifest or `archive.zip.sha256` for a checksum
+    pub fn sidecar(&self, extension: &str) -> PathBuf {
+        let mut path = self.output.clone().into_os_string();
+        path.push(".");
+        path.push(extension);
+        PathBuf::from(path)
+    }
+
+    /// The output and the files written next to it
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        let mut outputs = vec![self.output.clone()];
+        outputs.extend(self.sidecars.iter().map(|x| self.sidecar(x.extension())));
+        if let Some(format) = self.manifest {
+            if self.manifest_location == ManifestLocation::Sidecar {
+                outputs.push(self.sidecar(format.file_name()));
+            }
+        }
+        outputs
+    }
 }
 
-impl From<args::CLI> for Config {
-    fn from(args: CLI) -> Self {
-        let default_input = std::fs::canonicalize(PathBuf::from("./")).unwrap();
-        let default_output = PathBuf::from(format!("./{}.zip", parent_dir_name(&default_input)));
+fn utf8(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::input(format!("{} is not valid UTF-8", path.display())))
+}
+
+/// Fill in `{name}`, the name of the input directory, and `{profile}`
+fn output_name(output: PathBuf, input: &Path, profile: Option<&str>) -> Result<PathBuf> {
+    let Some(template) = output.to_str().filter(|x| x.contains('{')) else {
+        return Ok(output);
+    };
+    let name = template.replace("{name}", &parent_dir_name(input)?);
+    Ok(PathBuf::from(
+        name.replace("{profile}", profile.unwrap_or("default")),
+    ))
+}
+
+impl Config {
+    /// The current directory as input, zipped into `./<directory name>.zip`
+    pub fn new() -> Result<Self> {
+        let default_input = std::fs::canonicalize("./").with_path("./")?;
+        let default_output = PathBuf::from(format!("./{}.zip", parent_dir_name(&default_input)?));
+        Ok(Config {
+            inputs: vec![Input::new(default_input)],
+            output: default_output,
+            depth: None,
+            ignore_hidden: false,
+            read_gitignore: false,
+            parent: false,
+            compression_method: zip::CompressionMethod::Deflated,
+            compression_level: None,
+            digests: vec![Algorithm::Md5, Algorithm::Sha256],
+            sidecars: vec![],
+            sidecar_format: ChecksumFormat::Gnu,
+            manifest: None,
+            manifest_location: ManifestLocation::Embed,
+            reproducible: false,
+            source_date_epoch: None,
+            on_error: OnError::Abort,
+            // as unreadable directories were always left out, but now with a warning
+            on_walk_error: OnError::Warn,
+            on_special_file: OnError::Warn,
+            jobs: std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            stats: false,
+            quiet: false,
+            max_file_size: None,
+            newer_than: None,
+            older_than: None,
+            types: vec![],
+            includes: vec![],
+            excludes: vec![],
+            symlinks: SymlinkMode::Follow,
+        })
+    }
+}
+
+impl Config {
+    /// The defaults, overridden by the configuration files
+    fn layered(args: &ConfigArgs, input: Option<&Path>) -> Result<Self> {
+        let default = Config::new()?;
+        if args.no_config {
+            return Ok(default);
+        }
+        let directory = input.unwrap_or(Path::new("./"));
+        let file = ConfigFile::resolve(directory, args.config.as_deref(), args.profile.as_deref())?;
+        let invalid = |err: String| Error::input(format!("configuration: {}", err));
+        let parse_time = |x: Option<String>| x.as_deref().map(filter::parse_time).transpose();
+        Ok(Config {
+            inputs: file.input.unwrap_or(default.inputs),
+            output: file.output.unwrap_or(default.output),
+            depth: file.depth.or(default.depth),
+            ignore_hidden: file.ignore_hidden.unwrap_or(default.ignore_hidden),
+            read_gitignore: file.read_gitignore.unwrap_or(default.read_gitignore),
+            parent: file.parent.unwrap_or(default.parent),
+            compression_method: file
+                .compression_method
+                .map(Into::into)
+                .unwrap_or(default.compression_method),
+            compression_level: file.compression_level.or(default.compression_level),
+            digests: file.digest.unwrap_or(default.digests),
+            sidecars: file.sidecar.unwrap_or(default.sidecars),
+            sidecar_format: file.sidecar_format.unwrap_or(default.sidecar_format),
+            manifest: file.manifest.or(default.manifest),
+            manifest_location: file.manifest_location.unwrap_or(default.manifest_location),
+            reproducible: file.reproducible.unwrap_or(default.reproducible),
+            source_date_epoch: file.source_date_epoch.or(default.source_date_epoch),
+            on_error: file.on_error.unwrap_or(default.on_error),
+            on_walk_error: file.on_error.unwrap_or(default.on_walk_error),
+            on_special_file: file.on_special_file.unwrap_or(default.on_special_file),
+            jobs: file.jobs.unwrap_or(default.jobs),
+            stats: file.stats.unwrap_or(default.stats),
+            quiet: file.quiet.unwrap_or(default.quiet),
+            max_file_size: match file.max_file_size {
+                Some(size) => Some(filter::parse_size(&size).map_err(invalid)?),
+                None => default.max_file_size,
+            },
+            newer_than: parse_time(file.newer_than).map_err(invalid)?,
+            older_than: parse_time(file.older_than).map_err(invalid)?,
+            types: file.types.unwrap_or(default.types),
+            includes: file.include.unwrap_or(default.includes),
+            excludes: file.exclude.unwrap_or(default.excludes),
+            symlinks: file.symlinks.unwrap_or(default.symlinks),
+        })
+    }
+
+    /// Reject include and exclude patterns which aren't valid globs and
+    /// unknown file types
+    fn checked(self) -> Result<Self> {
+        filter::types(&self.types)?;
+        for input in &self.inputs {
+            crate::fs::overrides(&input.path, &self)?;
+        }
+        Ok(self)
+    }
+
+    /// Flags given on the command line win, a flag left off keeps the file's
+    /// value. Patterns add up.
+    fn walk(mut self, args: WalkArgs) -> Self {
+        self.includes.extend(args.include);
+        self.excludes.extend(args.exclude);
+        Config {
+            depth: args.depth.or(self.depth),
+            max_file_size: args.max_file_size.or(self.max_file_size),
+            newer_than: args.newer_than.or(self.newer_than),
+            older_than: args.older_than.or(self.older_than),
+            symlinks: args.symlinks.unwrap_or(self.symlinks),
+            types: if args.types.is_empty() {
+                self.types
+            } else {
+                args.types
+            },
+            ignore_hidden: args
+                .ignore_hidden
+                .or(args.no_ignore_hidden)
+                .unwrap_or(self.ignore_hidden),
+            read_gitignore: args
+                .read_gitignore
+                .or(args.no_read_gitignore)
+                .unwrap_or(self.read_gitignore),
+            ..self
+        }
+    }
+}
+
+impl TryFrom<ZipArgs> for Config {
+    type Error = Error;
+
+    fn try_from(args: ZipArgs) -> Result<Self> {
+        let first = args.input.input.first().map(|x| x.path.clone());
+        let layered = Config::layered(&args.config, first.as_deref())?.walk(args.walk);
+        let sidecars = if args.sidecar.is_empty() {
+            layered.sidecars
+        } else {
+            args.sidecar
+        };
+        let inputs = inputs(args.input, layered.inputs)?;
+        let output = args.output.unwrap_or(layered.output);
+        let profile = args.config.profile.as_deref();
+        Config {
+            output: match inputs.first() {
+                Some(first) => output_name(output, &first.path, profile)?,
+                None => output,
+            },
+            inputs,
+            parent: args.parent.or(args.no_parent).unwrap_or(layered.parent),
+            compression_method: args
+                .compression_method
+                .map(Into::into)
+                .unwrap_or(layered.compression_method),
+            // a level is only meaningful for the method it was chosen with
+            compression_level: match args.compression_method {
+                Some(_) => args.compression_level,
+                None => args.compression_level.or(layered.compression_level),
+            },
+            digests: args.digest.unwrap_or(layered.digests),
+            sidecars,
+            sidecar_format: args.sidecar_format.unwrap_or(layered.sidecar_format),
+            manifest: args.manifest.or(layered.manifest),
+            manifest_location: args.manifest_location.unwrap_or(layered.manifest_location),
+            reproducible: args
+                .reproducible
+                .or(args.no_reproducible)
+                .unwrap_or(layered.reproducible),
+            source_date_epoch: std::env::var("SOURCE_DATE_EPOCH")
+                .ok()
+                .and_then(|x| x.trim().parse().ok())
+                .or(layered.source_date_epoch),
+            on_error: args.on_error.unwrap_or(layered.on_error),
+            on_walk_error: args.on_error.unwrap_or(layered.on_walk_error),
+            on_special_file: args.on_special_file.unwrap_or(layered.on_special_file),
+            jobs: args.jobs.unwrap_or(layered.jobs),
+            stats: args.stats.or(args.no_stats).unwrap_or(layered.stats),
+            quiet: args.quiet.or(args.no_quiet).unwrap_or(layered.quiet),
+            ..layered
+        }
+        .checked()
+    }
+}
+
+impl TryFrom<TreeArgs> for Config {
+    type Error = Error;
+
+    fn try_from(args: TreeArgs) -> Result<Self> {
+        let first = args.input.input.first().map(|x| x.path.clone());
+        let layered = Config::layered(&args.config, first.as_deref())?.walk(args.walk);
         Config {
-            input: args.input.unwrap_or(default_input),
-            output: args.output.unwrap_or(default_output),
-            depth: args.depth,
-            ignore_hidden: args.ignore_hidden,
-            read_gitignore: args.read_gitignore,
-            parent: args.parent,
-            compression_method: args.compression_method.into(),
-            compression_level: args.compression_level,
+            inputs: inputs(args.input, layered.inputs)?,
+            ..layered
         }
+        .checked()
+    }
+}
+
+impl TryFrom<ExplainArgs> for Config {
+    type Error = Error;
+
+    fn try_from(args: ExplainArgs) -> Result<Self> {
+        Config::try_from(TreeArgs {
+            input: args.input,
+            walk: args.walk,
+            config: args.config,
+            show_ignored: false,
+        })
+    }
+}
+
+impl TryFrom<VerifyArgs> for Config {
+    type Error = Error;
+
+    fn try_from(args: VerifyArgs) -> Result<Self> {
+        let first = args.source.first().map(|x| x.path.clone());
+        let layered = Config::layered(&args.config, first.as_deref())?.walk(args.walk);
+        let source = InputArgs {
+            input: args.source,
+            files_from: None,
+        };
+        Config {
+            inputs: inputs(source, layered.inputs)?,
+            output: args.archive,
+            parent: args.parent.or(args.no_parent).unwrap_or(layered.parent),
+            digests: args.digest.unwrap_or(layered.digests),
+            ..layered
+        }
+        .checked()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::args::{Command, CLI};
+    use crate::config::{Config, Input};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    #[test]
+    fn input() {
+        assert_eq!(Input::parse("build/lib"), Ok(Input::new("build/lib")));
+        let input = Input::parse("build/lib=dist/lib").unwrap();
+        assert_eq!(input.path, PathBuf::from("build/lib"));
+        assert_eq!(input.prefix, PathBuf::from("dist/lib"));
+        assert_eq!(Input::parse("build="), Ok(Input::new("build")));
+        assert!(Input::parse("build=../dist").is_err());
+    }
+
+    #[test]
+    fn listed() {
+        let input = Input::listed("/no/such/dir/a.txt").unwrap();
+        assert_eq!(input.prefix, PathBuf::from("no/such/dir"));
+        assert_eq!(Input::listed("a.txt").unwrap().prefix, PathBuf::new());
+        assert!(Input::listed("../a.txt").is_err());
+    }
+
+    #[test]
+    fn negated_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lasagna.toml");
+        std::fs::write(
+            &file,
+            "parent = true\nquiet = true\nread-gitignore = true\n",
+        )
+        .unwrap();
+        let config = |flags: &[&str]| {
+            let mut args = vec!["lasagna", "--config", file.to_str().unwrap()];
+            args.extend(["-i", dir.path().to_str().unwrap()]);
+            args.extend(flags);
+            let Command::Zip(args) = CLI::parse_from(args).command() else {
+                unreachable!()
+            };
+            Config::try_from(args).unwrap()
+        };
+
+        let layered = config(&[]);
+        assert!(layered.parent && layered.quiet && layered.read_gitignore);
+        let negated = config(&["--no-parent", "--no-quiet", "--no-read-gitignore"]);
+        assert!(!negated.parent && !negated.quiet && !negated.read_gitignore);
+        // the last of a flag and its negation wins
+        assert!(config(&["--no-parent", "--parent"]).parent);
     }
 }
@@ -0,0 +1,333 @@
+use crate::config::Input;
+use crate::digest::{Algorithm, ChecksumFormat};
+use crate::error::{Context, Error, OnError, Result};
+use crate::fs::SymlinkMode;
+use crate::manifest::{ManifestFormat, ManifestLocation};
+use crate::wrapper;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// Names of a project configuration, looked up from the input directory upward
+const PROJECT_FILES: [&str; 2] = ["lasagna.toml", ".lasagnarc"];
+
+/// Every field of [`crate::Config`], all optional so that files can be layered.
+///
+/// Keys are the long flags without the dashes. Named profiles take the same
+/// keys and are layered on top when selected with `--profile`, e.g.
+/// ```toml
+/// read-gitignore = true
+/// compression-method = "zstd"
+/// compression-level = 19
+///
+/// [profile.release]
+/// exclude = ["*.log", "tests/"]
+/// output = "dist/{name}-{profile}.zip"
+/// parent = true
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    /// `"src"` or a list such as `["src", "build/lib=dist/lib"]`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub input: Option<Vec<Input>>,
+    pub output: Option<PathBuf>,
+    pub depth: Option<usize>,
+    pub ignore_hidden: Option<bool>,
+    pub read_gitignore: Option<bool>,
+    pub parent: Option<bool>,
+    pub compression_method: Option<wrapper::CompressionMethod>,
+    pub compression_level: Option<i64>,
+    pub digest: Option<Vec<Algorithm>>,
+    pub sidecar: Option<Vec<Algorithm>>,
+    pub sidecar_format: Option<ChecksumFormat>,
+    pub manifest: Option<ManifestFormat>,
+    pub manifest_location: Option<ManifestLocation>,
+    pub reproducible: Option<bool>,
+    pub source_date_epoch: Option<i64>,
+    pub on_error: Option<OnError>,
+    pub on_special_file: Option<OnError>,
+    pub jobs: Option<NonZeroUsize>,
+    pub stats: Option<bool>,
+    pub quiet: Option<bool>,
+    /// e.g. `"50M"`
+    pub max_file_size: Option<String>,
+    /// A date or an age, e.g. `"2026-01-01"` or `"30d"`
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    #[serde(rename = "type")]
+    pub types: Option<Vec<String>>,
+    /// Patterns in .gitignore syntax to restrict the archive to
+    pub include: Option<Vec<String>>,
+    /// Patterns in .gitignore syntax to leave out
+    pub exclude: Option<Vec<String>>,
+    pub symlinks: Option<SymlinkMode>,
+    #[serde(default)]
+    pub profile: BTreeMap<String, ConfigFile>,
+}
+
+/// A single value or a list of them
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<Input>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Input),
+        Many(Vec<Input>),
+    }
+    Ok(Some(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(input) => vec![input],
+        OneOrMany::Many(inputs) => inputs,
+    }))
+}
+
+impl ConfigFile {
+    /// Parse a file, `input` and `output` are relative to the file's directory
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<ConfigFile> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).with_path(path)?;
+        let mut file = ConfigFile::parse(&content)
+            .map_err(|err| Error::input(format!("{}: {}", path.display(), err)))?;
+        if file.profile.values().any(|x| !x.profile.is_empty()) {
+            return Err(Error::input(format!(
+                "{}: profiles can't be nested",
+                path.display()
+            )));
+        }
+        file.relative_to(path.parent().unwrap_or(Path::new("")));
+        Ok(file)
+    }
+
+    fn relative_to(&mut self, base: &Path) {
+        self.input = self
+            .input
+            .take()
+            .map(|x| x.into_iter().map(|x| x.relative_to(base)).collect());
+        self.output = self.output.take().map(|x| base.join(x));
+        for profile in self.profile.values_mut() {
+            profile.relative_to(base);
+        }
+    }
+
+    fn parse(content: &str) -> std::result::Result<ConfigFile, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    /// The nearest project configuration in `directory` or any of its ancestors
+    pub fn discover<T: AsRef<Path>>(directory: T) -> Option<PathBuf> {
+        let directory = std::fs::canonicalize(directory).ok()?;
+        directory.ancestors().find_map(|dir| {
+            PROJECT_FILES
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+        })
+    }
+
+    /// `$XDG_CONFIG_HOME/lasagna/config.toml`, `~/.config/lasagna/config.toml`
+    /// or `~/.lasagnarc`, whichever comes first
+    pub fn user() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|x| x.join(".config")));
+        let candidates = [
+            config_home.map(|x| x.join("lasagna").join("config.toml")),
+            home.map(|x| x.join(".lasagnarc")),
+        ];
+        candidates.into_iter().flatten().find(|path| path.is_file())
+    }
+
+    /// Layer the user configuration under the project one, which is either
+    /// `explicit` or discovered from `directory`
+    pub fn resolve(
+        directory: &Path,
+        explicit: Option<&Path>,
+        profile: Option<&str>,
+    ) -> Result<ConfigFile> {
+        let user = ConfigFile::user();
+        let project = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            // the user file may be found again when walking up from the home directory
+            None => ConfigFile::discover(directory).filter(|x| {
+                let canonical = user.as_ref().and_then(|x| std::fs::canonicalize(x).ok());
+                Some(x) != canonical.as_ref()
+            }),
+        };
+
+        let mut merged = ConfigFile::default();
+        for path in [user, project].into_iter().flatten() {
+            merged = merged.merge(ConfigFile::load(path)?);
+        }
+        match profile {
+            Some(name) => merged.select(name),
+            None => Ok(merged),
+        }
+    }
+
+    /// Layer the profile `name` on top of the rest of the file
+    pub fn select(mut self, name: &str) -> Result<ConfigFile> {
+        let Some(profile) = self.profile.remove(name) else {
+            let known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+            return Err(Error::input(format!(
+                "unknown profile {}, expected one of: {}",
+                name,
+                known.join(", ")
+            )));
+        };
+        self.profile.clear();
+        Ok(self.merge(profile))
+    }
+
+    /// Fields set in `other` take precedence
+    pub fn merge(self, other: ConfigFile) -> ConfigFile {
+        ConfigFile {
+            input: other.input.or(self.input),
+            output: other.output.or(self.output),
+            depth: other.depth.or(self.depth),
+            ignore_hidden: other.ignore_hidden.or(self.ignore_hidden),
+            read_gitignore: other.read_gitignore.or(self.read_gitignore),
+            parent: other.parent.or(self.parent),
+            compression_method: other.compression_method.or(self.compression_method),
+            compression_level: other.compression_level.or(self.compression_level),
+            digest: other.digest.or(self.digest),
+            sidecar: other.sidecar.or(self.sidecar),
+            sidecar_format: other.sidecar_format.or(self.sidecar_format),
+            manifest: other.manifest.or(self.manifest),
+            manifest_location: other.manifest_location.or(self.manifest_location),
+            reproducible: other.reproducible.or(self.reproducible),
+            source_date_epoch: other.source_date_epoch.or(self.source_date_epoch),
+            on_error: other.on_error.or(self.on_error),
+            on_special_file: other.on_special_file.or(self.on_special_file),
+            jobs: other.jobs.or(self.jobs),
+            stats: other.stats.or(self.stats),
+            quiet: other.quiet.or(self.quiet),
+            max_file_size: other.max_file_size.or(self.max_file_size),
+            newer_than: other.newer_than.or(self.newer_than),
+            older_than: other.older_than.or(self.older_than),
+            types: other.types.or(self.types),
+            include: other.include.or(self.include),
+            exclude: other.exclude.or(self.exclude),
+            symlinks: other.symlinks.or(self.symlinks),
+            profile: {
+                // profiles of the same name are merged key by key
+                let mut profile = self.profile;
+                for (name, other) in other.profile {
+                    let merged = match profile.remove(&name) {
+                        Some(base) => base.merge(other),
+                        None => other,
+                    };
+                    profile.insert(name, merged);
+                }
+                profile
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Input;
+    use crate::config_file::ConfigFile;
+    use crate::digest::Algorithm;
+    use crate::error::OnError;
+    use std::path::Path;
+
+    #[test]
+    fn parse() {
+        let file = ConfigFile::parse(
+            r#"
+            read-gitignore = true
+            ignore-hidden = true
+            compression-method = "zstd"
+            compression-level = 19
+            digest = ["sha256", "blake3"]
+            on-error = "warn"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.read_gitignore, Some(true));
+        assert_eq!(file.ignore_hidden, Some(true));
+        assert!(matches!(
+            file.compression_method,
+            Some(crate::wrapper::CompressionMethod::Zstd)
+        ));
+        assert_eq!(file.compression_level, Some(19));
+        assert_eq!(
+            file.digest,
+            Some(vec![Algorithm::Sha256, Algorithm::Blake3])
+        );
+        assert_eq!(file.on_error, Some(OnError::Warn));
+        assert_eq!(file.parent, None);
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(ConfigFile::parse("compresion-level = 1").is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let user = ConfigFile::parse("depth = 1\nparent = true").unwrap();
+        let project = ConfigFile::parse("depth = 2").unwrap();
+        let merged = user.merge(project);
+        assert_eq!(merged.depth, Some(2));
+        assert_eq!(merged.parent, Some(true));
+    }
+
+    #[test]
+    fn profile() {
+        let file = ConfigFile::parse(
+            r#"
+            compression-method = "zstd"
+            compression-level = 19
+            exclude = ["*.log"]
+
+            [profile.backup]
+            compression-level = 3
+            parent = true
+
+            [profile.source]
+            exclude = ["target/"]
+            "#,
+        )
+        .unwrap();
+        let backup = ConfigFile::parse(
+            "[profile.backup]
+output = \"{name}.zip\"",
+        )
+        .unwrap();
+        let file = file.merge(backup);
+
+        let selected = file.select("backup").unwrap();
+        assert_eq!(selected.compression_level, Some(3));
+        assert_eq!(selected.parent, Some(true));
+        assert_eq!(selected.output, Some("{name}.zip".into()));
+        assert_eq!(selected.exclude, Some(vec!["*.log".to_string()]));
+        assert!(selected.profile.is_empty());
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let file = ConfigFile::parse("[profile.source]\ndepth = 1").unwrap();
+        let err = file.select("release").unwrap_err();
+        assert!(err.to_string().contains("source"));
+    }
+
+    #[test]
+    fn inputs() {
+        let single = ConfigFile::parse("input = \"src\"").unwrap();
+        assert_eq!(single.input, Some(vec![Input::new("src")]));
+
+        let mut file = ConfigFile::parse("input = [\"src\", \"build/lib=dist/lib\"]").unwrap();
+        file.relative_to(Path::new("/project"));
+        let inputs = file.input.unwrap();
+        assert_eq!(inputs[0], Input::new("/project/src"));
+        assert_eq!(inputs[1].path, Path::new("/project/build/lib"));
+        assert_eq!(inputs[1].prefix, Path::new("dist/lib"));
+
+        assert!(ConfigFile::parse("input = \"src=../up\"").is_err());
+    }
+}
@@ -1,83 +1,456 @@
+use crate::error::{Context, Result};
+use clap::ValueEnum;
 use md5::{Digest, Md5};
-use sha2::Sha256;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
 use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 // use hex_literal::hex;
 
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake3,
+    Crc32,
+}
+
+impl Algorithm {
+    const ALL: [Algorithm; 6] = [
+        Algorithm::Md5,
+        Algorithm::Sha1,
+        Algorithm::Sha256,
+        Algorithm::Sha512,
+        Algorithm::Blake3,
+        Algorithm::Crc32,
+    ];
+
+    /// Tag used by the BSD format, e.g. `SHA256 (archive.zip) = ...`
+    pub fn tag(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+            Algorithm::Blake3 => "BLAKE3",
+            Algorithm::Crc32 => "CRC32",
+        }
+    }
+
+    /// Extension of the sidecar file, e.g. `archive.zip.sha256`
+    pub fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Crc32 => "crc32",
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Algorithm> {
+        Algorithm::ALL.into_iter().find(|x| x.tag() == tag)
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Algorithm> {
+        let extension = extension.to_ascii_lowercase();
+        Algorithm::ALL
+            .into_iter()
+            .find(|x| x.extension() == extension)
+    }
+
+    /// Guess the algorithm from the length of a hex digest, 64 digits are
+    /// taken as SHA256 since BLAKE3 sidecars are told apart by their tag or extension
+    pub fn from_hex_len(len: usize) -> Option<Algorithm> {
+        match len {
+            8 => Some(Algorithm::Crc32),
+            32 => Some(Algorithm::Md5),
+            40 => Some(Algorithm::Sha1),
+            64 => Some(Algorithm::Sha256),
+            128 => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// Incremental state of one of the [`Algorithm`]s
+enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::default()),
+            Algorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Crc32(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChecksumFormat {
+    /// `<digest>  <file>`, as written by `sha256sum` and `md5sum`
+    Gnu,
+    /// `SHA256 (<file>) = <digest>`, as written by `shasum --tag` and BSD `sha256`
+    Bsd,
+}
+
+/// A single line of a checksum file, either in GNU or BSD format
+#[derive(PartialEq, Debug)]
+pub struct ChecksumLine {
+    pub algorithm: Option<Algorithm>,
+    pub digest: String,
+    pub filename: String,
+}
+
+impl ChecksumLine {
+    pub fn parse(line: &str) -> Option<ChecksumLine> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        // BSD: `TAG (file) = digest`
+        if let Some((tag, rest)) = line.split_once(" (") {
+            if let Some((filename, digest)) = rest.rsplit_once(") = ") {
+                return Some(ChecksumLine {
+                    algorithm: Some(Algorithm::from_tag(tag)?),
+                    digest: digest.to_ascii_lowercase(),
+                    filename: filename.to_string(),
+                });
+            }
+        }
+        // GNU: `digest  file`, or `digest *file` in binary mode
+        let (digest, filename) = line.split_once(' ')?;
+        let filename = filename.strip_prefix(['*', ' ']).unwrap_or(filename);
+        if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(ChecksumLine {
+            algorithm: None,
+            digest: digest.to_ascii_lowercase(),
+            filename: filename.to_string(),
+        })
+    }
+}
+
 pub struct ZipDigest {
     path: PathBuf,
-    md5: [u8; 16],
-    sha256: [u8; 32],
+    digests: Vec<(Algorithm, Vec<u8>)>,
 }
 
-impl ZipDigest {
-    pub fn new<T: Into<PathBuf>>(path: T) -> Self {
-        ZipDigest {
+/// A writer computing the digests of everything written through it, so that
+/// the archive doesn't need to be read again once it has been written.
+///
+/// Bytes are hashed as they are written, so seeking back and overwriting them
+/// is refused: the writer has to produce the file strictly in order.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    algorithms: Vec<Algorithm>,
+    hashers: Vec<Hasher>,
+    /// Current position of the inner writer
+    position: u64,
+    /// Number of bytes hashed so far, i.e. the end of the file
+    hashed: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, algorithms: &[Algorithm]) -> HashingWriter<W> {
+        let mut unique: Vec<Algorithm> = vec![];
+        for algorithm in algorithms {
+            if !unique.contains(algorithm) {
+                unique.push(*algorithm);
+            }
+        }
+        HashingWriter {
+            inner,
+            hashers: unique.iter().map(|x| Hasher::new(*x)).collect(),
+            algorithms: unique,
+            position: 0,
+            hashed: 0,
+        }
+    }
+
+    /// Flush the inner writer and return it along with the digests of `path`
+    pub fn finish<T: Into<PathBuf>>(mut self, path: T) -> io::Result<(W, ZipDigest)> {
+        self.inner.flush()?;
+        let digest = ZipDigest {
             path: path.into(),
-            md5: [0; 16],
-            sha256: [0; 32],
+            digests: self
+                .algorithms
+                .into_iter()
+                .zip(self.hashers.into_iter().map(Hasher::finalize))
+                .collect(),
+        };
+        Ok((self.inner, digest))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position != self.hashed {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "bytes which have already been hashed can't be overwritten",
+            ));
         }
-        .md5()
-        .sha256()
+        let count = self.inner.write(buf)?;
+        for hasher in self.hashers.iter_mut() {
+            hasher.update(&buf[..count]);
+        }
+        self.position += count as u64;
+        self.hashed = self.position;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Seek> Seek for HashingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(0) => Some(self.position),
+            SeekFrom::End(0) => Some(self.hashed),
+            _ => None,
+        };
+        // answer position queries without touching the inner writer,
+        // a `BufWriter` would flush its buffer on every one of them
+        if target == Some(self.position) {
+            return Ok(self.position);
+        }
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+impl ZipDigest {
+    /// Compute all the requested digests in a single pass over the file
+    pub fn new<T: Into<PathBuf>>(path: T, algorithms: &[Algorithm]) -> Result<Self> {
+        let path = path.into();
+        let file = File::open(&path).with_path(&path)?;
+        let mut reader = BufReader::new(file);
+        let mut writer = HashingWriter::new(io::sink(), algorithms);
+        io::copy(&mut reader, &mut writer).with_path(&path)?;
+        Ok(writer.finish(&path).with_path(&path)?.1)
     }
 
     pub fn display(&self) {
         println!("{}", self);
     }
 
-    pub fn md5(mut self) -> Self {
-        let file = File::open(&self.path).unwrap();
-        let mut reader = BufReader::new(file);
+    pub fn get(&self, algorithm: Algorithm) -> Option<&[u8]> {
+        self.digests
+            .iter()
+            .find(|(x, _)| *x == algorithm)
+            .map(|(_, digest)| digest.as_slice())
+    }
+
+    fn file_name(&self) -> String {
+        let file_name = self.path.file_name().unwrap_or(self.path.as_os_str());
+        file_name.to_string_lossy().to_string()
+    }
 
-        let digest = {
-            let mut hasher = Md5::new();
-            let mut buffer = [0; 1024];
-            loop {
-                let count = reader.read(&mut buffer).unwrap();
-                if count == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..count]);
+    /// Write `<archive>.<algorithm>` next to the archive, return its path
+    pub fn write_sidecar(&self, algorithm: Algorithm, format: ChecksumFormat) -> Result<PathBuf> {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".");
+        path.push(algorithm.extension());
+        let path = PathBuf::from(path);
+
+        let digest = match self.get(algorithm) {
+            Some(digest) => digest.to_vec(),
+            None => {
+                ZipDigest::new(&self.path, &[algorithm])?
+                    .digests
+                    .remove(0)
+                    .1
             }
-            hasher.finalize()
         };
-
-        // see https://stackoverflow.com/questions/59376378/how-can-i-turn-a-genericarrayt-into-an-array-of-the-same-length
-        self.md5 = digest.as_slice().try_into().unwrap();
-        self
+        let digest = base16ct::lower::encode_string(&digest);
+        let line = match format {
+            ChecksumFormat::Gnu => format!("{}  {}\n", digest, self.file_name()),
+            ChecksumFormat::Bsd => {
+                format!("{} ({}) = {}\n", algorithm.tag(), self.file_name(), digest)
+            }
+        };
+        std::fs::write(&path, line).with_path(&path)?;
+        Ok(path)
     }
 
-    pub fn sha256(mut self) -> Self {
-        let file = File::open(&self.path).unwrap();
-        let mut reader = BufReader::new(file);
+    /// Check the archive against a sidecar in either format, print the result
+    /// the way `sha256sum -c` does and return whether it matched
+    pub fn check_sidecar<T: AsRef<Path>>(&self, sidecar: T) -> Result<bool> {
+        let sidecar = sidecar.as_ref();
+        let content = std::fs::read_to_string(sidecar).with_path(sidecar)?;
+        let lines: Vec<ChecksumLine> = content.lines().filter_map(ChecksumLine::parse).collect();
 
-        let digest = {
-            let mut hasher = Sha256::new();
-            let mut buffer = [0; 1024];
-            loop {
-                let count = reader.read(&mut buffer).unwrap();
-                if count == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..count]);
-            }
-            hasher.finalize()
+        // a sidecar with a single line may refer to the archive by another name
+        let line = match lines.iter().find(|x| x.filename == self.file_name()) {
+            Some(line) => Some(line),
+            None if lines.len() == 1 => lines.first(),
+            None => None,
+        };
+        let Some(line) = line else {
+            println!(
+                "{}: no checksum found for {}",
+                sidecar.display(),
+                self.file_name()
+            );
+            return Ok(false);
         };
 
-        // see https://stackoverflow.com/questions/59376378/how-can-i-turn-a-genericarrayt-into-an-array-of-the-same-length
-        self.sha256 = digest.as_slice().try_into().unwrap();
-        self
+        let algorithm = line
+            .algorithm
+            .or_else(|| {
+                sidecar
+                    .extension()
+                    .and_then(|x| x.to_str())
+                    .and_then(Algorithm::from_extension)
+            })
+            .or_else(|| Algorithm::from_hex_len(line.digest.len()));
+        let ok = match algorithm {
+            Some(algorithm) => {
+                let digest = match self.get(algorithm) {
+                    Some(digest) => digest.to_vec(),
+                    None => {
+                        ZipDigest::new(&self.path, &[algorithm])?
+                            .digests
+                            .remove(0)
+                            .1
+                    }
+                };
+                base16ct::lower::encode_string(&digest) == line.digest
+            }
+            None => false,
+        };
+        println!(
+            "{}: {}",
+            self.path.display(),
+            if ok { "OK" } else { "FAILED" }
+        );
+        Ok(ok)
     }
 }
 
 impl Display for ZipDigest {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let md5 = base16ct::lower::encode_string(&self.md5);
-        writeln!(f, "MD5   : {}", md5)?;
-        let sha256 = base16ct::lower::encode_string(&self.sha256);
-        writeln!(f, "SHA256: {}", sha256)?;
+        for (algorithm, digest) in &self.digests {
+            let digest = base16ct::lower::encode_string(digest);
+            writeln!(f, "{:<6}: {}", algorithm.tag(), digest)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::digest::{Algorithm, ChecksumLine, HashingWriter};
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+
+    const SHA256: &str = "3014b8c89d63eceffb345b7e1d8ea90d17db313de4e0dd18747f1fc9bd65e64d";
+
+    #[test]
+    fn gnu_line() {
+        let line = ChecksumLine::parse(&format!("{}  archive.zip", SHA256)).unwrap();
+        assert_eq!(line.algorithm, None);
+        assert_eq!(line.digest, SHA256);
+        assert_eq!(line.filename, "archive.zip");
+    }
+
+    #[test]
+    fn gnu_binary_line() {
+        let line = ChecksumLine::parse(&format!("{} *archive.zip", SHA256)).unwrap();
+        assert_eq!(line.filename, "archive.zip");
+    }
+
+    #[test]
+    fn bsd_line() {
+        let line = ChecksumLine::parse(&format!("SHA256 (my archive.zip) = {}", SHA256)).unwrap();
+        assert_eq!(line.algorithm, Some(Algorithm::Sha256));
+        assert_eq!(line.digest, SHA256);
+        assert_eq!(line.filename, "my archive.zip");
+    }
+
+    #[test]
+    fn invalid_line() {
+        assert_eq!(ChecksumLine::parse("not a checksum"), None);
+        assert_eq!(ChecksumLine::parse(""), None);
+    }
+
+    #[test]
+    fn guess_algorithm() {
+        assert_eq!(
+            Algorithm::from_hex_len(SHA256.len()),
+            Some(Algorithm::Sha256)
+        );
+        assert_eq!(Algorithm::from_hex_len(32), Some(Algorithm::Md5));
+        assert_eq!(Algorithm::from_extension("SHA256"), Some(Algorithm::Sha256));
+        assert_eq!(Algorithm::from_extension("blake3"), Some(Algorithm::Blake3));
+        assert_eq!(Algorithm::from_hex_len(128), Some(Algorithm::Sha512));
+    }
+
+    #[test]
+    fn hashing_writer() {
+        let mut writer = HashingWriter::new(Cursor::new(vec![]), &[Algorithm::Crc32]);
+        writer.write_all(b"lasagna").unwrap();
+        assert_eq!(writer.stream_position().unwrap(), 7);
+        writer.seek(SeekFrom::Start(7)).unwrap();
+        writer.write_all(b"!").unwrap();
+
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        assert!(writer.write_all(b"L").is_err());
+        writer.seek(SeekFrom::End(0)).unwrap();
+
+        let (inner, digest) = writer.finish("lasagna.zip").unwrap();
+        assert_eq!(inner.into_inner(), b"lasagna!");
+        let expected = crc32fast::hash(b"lasagna!").to_be_bytes();
+        assert_eq!(digest.get(Algorithm::Crc32), Some(expected.as_slice()));
+    }
+
+    #[test]
+    fn bsd_tags() {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(Algorithm::from_tag(algorithm.tag()), Some(algorithm));
+        }
+    }
+}
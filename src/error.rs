@@ -0,0 +1,188 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zip::result::ZipError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The command line, the input or a sidecar is not usable
+    #[error("{0}")]
+    Input(String),
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}: {source}", path.display())]
+    Zip { path: PathBuf, source: ZipError },
+    /// The archive doesn't match what it was verified against
+    #[error("verification failed: {0}")]
+    Verification(String),
+}
+
+impl Error {
+    pub fn input<T: Into<String>>(message: T) -> Self {
+        Error::Input(message.into())
+    }
+
+    /// 1 for a failed verification, 2 for bad input (as clap does for bad
+    /// arguments) and 3 for an I/O failure
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::Verification(_) => ExitCode::from(1),
+            Error::Input(_) => ExitCode::from(2),
+            Error::Io { .. } | Error::Zip { .. } => ExitCode::from(3),
+        }
+    }
+}
+
+/// The path a walk error is about, if it carries one
+fn walk_error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        ignore::Error::Partial(errs) => errs.iter().find_map(walk_error_path),
+        _ => None,
+    }
+}
+
+/// The OS error code, possibly wrapped by the walker
+fn os_error(err: &io::Error) -> Option<i32> {
+    err.raw_os_error().or_else(|| {
+        let inner = err.get_ref()?.source()?.downcast_ref::<io::Error>()?;
+        inner.raw_os_error()
+    })
+}
+
+impl From<ignore::Error> for Error {
+    fn from(err: ignore::Error) -> Self {
+        let path = walk_error_path(&err)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        // the walker's own message repeats the path
+        let source = match err.io_error() {
+            Some(source) => match os_error(source) {
+                Some(code) => io::Error::from_raw_os_error(code),
+                None => io::Error::new(source.kind(), source.to_string()),
+            },
+            None => io::Error::other(err.to_string()),
+        };
+        Error::Io { path, source }
+    }
+}
+
+/// What to do when a file can't be walked, opened or read while zipping
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnError {
+    /// Stop without writing the archive
+    Abort,
+    /// Leave the file out of the archive, list it in the summary
+    Skip,
+    /// As skip, but also warn as soon as it happens
+    Warn,
+}
+
+impl OnError {
+    /// `Ok(None)` when the error is tolerated, the error itself otherwise
+    pub fn apply<T>(self, result: Result<T>, skipped: &mut Vec<Error>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self == OnError::Abort => Err(err),
+            Err(err) => {
+                if self == OnError::Warn {
+                    eprintln!("lasagna: warning: {}", err);
+                }
+                skipped.push(err);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Attach the path being worked on to an I/O or zip error
+pub trait Context<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })
+    }
+}
+
+impl<T> Context<T> for zip::result::ZipResult<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|source| match source {
+            ZipError::Io(source) => Error::Io {
+                path: path.as_ref().to_path_buf(),
+                source,
+            },
+            source => Error::Zip {
+                path: path.as_ref().to_path_buf(),
+                source,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::{Context, Error, OnError};
+    use std::io;
+    use std::process::ExitCode;
+    use zip::result::ZipError;
+
+    #[test]
+    fn exit_codes() {
+        let not_found = || io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(
+            Error::Verification("a.zip".into()).exit_code(),
+            ExitCode::from(1)
+        );
+        assert_eq!(Error::input("bad").exit_code(), ExitCode::from(2));
+        let io: io::Result<()> = Err(not_found());
+        assert_eq!(
+            io.with_path("a").unwrap_err().exit_code(),
+            ExitCode::from(3)
+        );
+        let zip: zip::result::ZipResult<()> = Err(ZipError::FileNotFound);
+        assert_eq!(
+            zip.with_path("a").unwrap_err().exit_code(),
+            ExitCode::from(3)
+        );
+    }
+
+    #[test]
+    fn on_error() {
+        let failed = || Err::<(), _>(Error::input("unreadable"));
+        let mut skipped = vec![];
+        assert!(OnError::Abort.apply(failed(), &mut skipped).is_err());
+        assert!(skipped.is_empty());
+        assert!(matches!(
+            OnError::Skip.apply(failed(), &mut skipped),
+            Ok(None)
+        ));
+        assert!(matches!(
+            OnError::Warn.apply(Ok(1), &mut skipped),
+            Ok(Some(1))
+        ));
+        assert_eq!(skipped.len(), 1);
+    }
+
+    #[test]
+    fn zip_io_error_keeps_the_path() {
+        let zip: zip::result::ZipResult<()> =
+            Err(ZipError::Io(io::Error::from(io::ErrorKind::NotFound)));
+        let err = zip.with_path("a.zip").unwrap_err();
+        assert!(matches!(err, Error::Io { .. }));
+        assert!(err.to_string().starts_with("a.zip: "));
+    }
+}
@@ -0,0 +1,551 @@
+use crate::error::{Context, Error, Result};
+use crate::filter;
+use crate::fs::{overrides, SymlinkMode};
+use crate::Config;
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use ignore::overrides::Override;
+use ignore::types::Types;
+use ignore::Match;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Where a pattern comes from
+#[derive(Debug, PartialEq)]
+pub enum Origin {
+    /// An ignore file and the line of the pattern in it
+    File(PathBuf, Option<usize>),
+    /// An ignore pattern which was not read from a file
+    Unknown,
+    Include,
+    Exclude,
+}
+
+/// What decides whether a path is walked
+#[derive(Debug, PartialEq)]
+pub enum Rule {
+    /// Nothing matches the path, it is walked
+    Default,
+    /// An input is always walked
+    Input,
+    Pattern {
+        glob: String,
+        origin: Origin,
+    },
+    /// There are include patterns, none of which matches
+    NoInclude,
+    /// A file of the selected type, or of none of them
+    Type(Option<String>),
+    Hidden,
+    Depth(usize),
+    Size(u64),
+    Modified,
+    /// A link, with `--symlinks skip`
+    Symlink,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Default => write!(f, "as no rule matches"),
+            Rule::Input => write!(f, "as it is an input"),
+            Rule::Pattern { glob, origin } => match origin {
+                Origin::File(file, Some(line)) => {
+                    write!(f, "by `{}` ({}:{})", glob, file.display(), line)
+                }
+                Origin::File(file, None) => write!(f, "by `{}` ({})", glob, file.display()),
+                Origin::Unknown => write!(f, "by `{}`", glob),
+                Origin::Include => write!(f, "by --include `{}`", glob),
+                Origin::Exclude => write!(f, "by --exclude `{}`", glob),
+            },
+            Rule::NoInclude => write!(f, "as it matches no --include pattern"),
+            Rule::Type(Some(name)) => write!(f, "by --type {}", name),
+            Rule::Type(None) => write!(f, "as it matches none of the --type types"),
+            Rule::Hidden => write!(f, "as it is hidden"),
+            Rule::Depth(depth) => write!(f, "as it is deeper than --depth {}", depth),
+            Rule::Size(size) => write!(f, "as it is larger than --max-file-size ({} bytes)", size),
+            Rule::Modified => write!(f, "as it was modified outside --newer-than/--older-than"),
+            Rule::Symlink => write!(f, "as it is a symlink, see --symlinks"),
+        }
+    }
+}
+
+impl Rule {
+    /// A short name of what the rule comes from, e.g. `.zipignore` or `--exclude`
+    pub fn source(&self) -> String {
+        match self {
+            Rule::Default | Rule::Input => "none".to_string(),
+            Rule::Pattern { origin, .. } => match origin {
+                Origin::File(file, _) => file.file_name().map_or(file.display().to_string(), |x| {
+                    x.to_string_lossy().to_string()
+                }),
+                Origin::Unknown => "ignore".to_string(),
+                Origin::Include => "--include".to_string(),
+                Origin::Exclude => "--exclude".to_string(),
+            },
+            Rule::NoInclude => "--include".to_string(),
+            Rule::Type(_) => "--type".to_string(),
+            Rule::Hidden => "hidden".to_string(),
+            Rule::Depth(_) => "--depth".to_string(),
+            Rule::Size(_) => "--max-file-size".to_string(),
+            Rule::Modified => "--newer-than/--older-than".to_string(),
+            Rule::Symlink => "--symlinks".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Explanation {
+    pub path: PathBuf,
+    pub included: bool,
+    pub rule: Rule,
+    /// The ancestor the verdict comes from, if not the path itself
+    pub parent: Option<PathBuf>,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let verdict = if self.included {
+            "included"
+        } else {
+            "excluded"
+        };
+        match &self.parent {
+            Some(parent) => write!(
+                f,
+                "{}: {}, {} is {} {}",
+                self.path.display(),
+                verdict,
+                parent.display(),
+                verdict,
+                self.rule
+            ),
+            None => write!(f, "{}: {} {}", self.path.display(), verdict, self.rule),
+        }
+    }
+}
+
+/// The ignore files of a directory
+struct DirRules {
+    zipignore: Gitignore,
+    ignore: Gitignore,
+    gitignore: Gitignore,
+    /// `.git/info/exclude`
+    exclude: Gitignore,
+    has_git: bool,
+}
+
+struct InputRules {
+    /// The input as given, to display paths
+    path: PathBuf,
+    root: PathBuf,
+    overrides: Override,
+    /// The same patterns as `overrides`, to tell which one matched
+    patterns: Gitignore,
+}
+
+/// Tell why a path is walked or not, following the same precedence as the
+/// walker: include and exclude patterns, then the ignore files from the
+/// deepest directory up, then file types, hidden files, size and age.
+pub struct Explainer<'a> {
+    config: &'a Config,
+    inputs: Vec<InputRules>,
+    types: Types,
+    global: Gitignore,
+    dirs: RefCell<HashMap<PathBuf, Rc<DirRules>>>,
+}
+
+impl<'a> Explainer<'a> {
+    pub fn new(config: &'a Config) -> Result<Explainer<'a>> {
+        let mut inputs = vec![];
+        for input in &config.inputs {
+            let root = std::fs::canonicalize(&input.path).with_path(&input.path)?;
+            let mut patterns = GitignoreBuilder::new(&root);
+            for pattern in &config.includes {
+                let _ = patterns.add_line(None, pattern);
+            }
+            for pattern in &config.excludes {
+                let _ = patterns.add_line(None, &format!("!{}", pattern));
+            }
+            inputs.push(InputRules {
+                path: input.path.clone(),
+                overrides: overrides(&root, config)?,
+                patterns: patterns.build().unwrap_or_else(|_| Gitignore::empty()),
+                root,
+            });
+        }
+        Ok(Explainer {
+            config,
+            inputs,
+            types: filter::types(&config.types)?,
+            global: Gitignore::global().0,
+            dirs: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Explain a path inside one of the inputs
+    pub fn explain<T: AsRef<Path>>(&self, path: T) -> Result<Explanation> {
+        let path = path.as_ref();
+        // resolve the parent only, the path itself may be a symlink
+        let absolute = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                std::fs::canonicalize(parent).with_path(parent)?.join(name)
+            }
+            _ => std::fs::canonicalize(path).with_path(path)?,
+        };
+        let Some(input) = self.inputs.iter().find(|x| absolute.starts_with(&x.root)) else {
+            return Err(Error::input(format!(
+                "{} is not in any input",
+                path.display()
+            )));
+        };
+
+        let relative = absolute.strip_prefix(&input.root).unwrap_or(Path::new(""));
+        let mut current = input.root.clone();
+        let (mut included, mut rule) = (true, Rule::Input);
+        for (depth, component) in relative.components().enumerate() {
+            current.push(component);
+            (included, rule) = self.check(input, &current, self.is_dir(&current), depth + 1);
+            if !included {
+                break;
+            }
+        }
+        // a left out directory takes everything inside along
+        let parent = (current != absolute)
+            .then(|| input.path.join(current.strip_prefix(&input.root).unwrap()));
+        Ok(Explanation {
+            path: path.to_path_buf(),
+            included,
+            rule,
+            parent,
+        })
+    }
+
+    /// Every path left out of the walk, without what is inside left out directories
+    pub fn ignored(&self) -> Vec<Explanation> {
+        let mut ignored = vec![];
+        for input in &self.inputs {
+            if input.root.is_dir() {
+                self.visit(input, &input.root, 0, &mut ignored);
+            }
+        }
+        ignored
+    }
+
+    fn visit(&self, input: &InputRules, dir: &Path, depth: usize, ignored: &mut Vec<Explanation>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries.flatten().map(|x| x.path()).collect();
+        paths.sort();
+        for path in paths {
+            let is_dir = self.is_dir(&path);
+            let (included, rule) = self.check(input, &path, is_dir, depth + 1);
+            if !included {
+                ignored.push(Explanation {
+                    path: input.path.join(path.strip_prefix(&input.root).unwrap()),
+                    included,
+                    rule,
+                    parent: None,
+                });
+            } else if is_dir {
+                self.visit(input, &path, depth + 1, ignored);
+            }
+        }
+    }
+
+    /// Check a single entry, assuming its parent directories are walked
+    fn check(&self, input: &InputRules, path: &Path, is_dir: bool, depth: usize) -> (bool, Rule) {
+        if let Some(max) = self.config.depth.filter(|x| depth > *x) {
+            return (false, Rule::Depth(max));
+        }
+
+        let mut whitelisted = None;
+        if !input.overrides.is_empty() {
+            let matched = input.overrides.matched(path, is_dir);
+            let pattern = input.patterns.matched(path, is_dir);
+            let glob = |origin| match pattern {
+                Match::Ignore(glob) | Match::Whitelist(glob) => Rule::Pattern {
+                    glob: glob.original().trim_start_matches('!').to_string(),
+                    origin,
+                },
+                Match::None => Rule::NoInclude,
+            };
+            if matched.is_ignore() {
+                return (false, glob(Origin::Exclude));
+            } else if matched.is_whitelist() {
+                whitelisted = Some(glob(Origin::Include));
+            }
+        }
+
+        if whitelisted.is_none() {
+            match self.ignore_files(path, is_dir) {
+                Match::Ignore(rule) => return (false, rule),
+                Match::Whitelist(rule) => whitelisted = Some(rule),
+                Match::None => {}
+            }
+            if !self.types.is_empty() {
+                match self.types.matched(path, is_dir) {
+                    Match::Ignore(_) => return (false, Rule::Type(None)),
+                    Match::Whitelist(glob) => {
+                        let name = glob.file_type_def().map(|x| x.name().to_string());
+                        whitelisted = Some(Rule::Type(name));
+                    }
+                    Match::None => {}
+                }
+            }
+        }
+
+        let hidden = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| x.starts_with('.'));
+        if whitelisted.is_none() && self.config.ignore_hidden && hidden {
+            return (false, Rule::Hidden);
+        }
+        if self.config.symlinks == SymlinkMode::Skip && path.is_symlink() {
+            return (false, Rule::Symlink);
+        }
+        if !is_dir {
+            if let Some(max) = self.config.max_file_size {
+                if std::fs::metadata(path).is_ok_and(|x| x.len() > max) {
+                    return (false, Rule::Size(max));
+                }
+            }
+            let (newer_than, older_than) = (self.config.newer_than, self.config.older_than);
+            if !filter::modified_within(path, newer_than, older_than) {
+                return (false, Rule::Modified);
+            }
+        }
+        (true, whitelisted.unwrap_or(Rule::Default))
+    }
+
+    /// The rule of the ignore files, the deepest directory winning for each
+    /// kind of file and .zipignore winning over .ignore over .gitignore
+    fn ignore_files(&self, path: &Path, is_dir: bool) -> Match<Rule> {
+        let rules: Vec<Rc<DirRules>> = path.ancestors().skip(1).map(|x| self.rules(x)).collect();
+        let any_git = rules.iter().any(|x| x.has_git);
+
+        let (mut zipignore, mut ignore, mut gitignore, mut exclude) =
+            (Match::None, Match::None, Match::None, Match::None);
+        let mut saw_git = false;
+        for dir in &rules {
+            if zipignore.is_none() {
+                zipignore = dir.zipignore.matched(path, is_dir);
+            }
+            if ignore.is_none() {
+                ignore = dir.ignore.matched(path, is_dir);
+            }
+            if any_git && !saw_git && gitignore.is_none() {
+                gitignore = dir.gitignore.matched(path, is_dir);
+            }
+            if any_git && !saw_git && exclude.is_none() {
+                exclude = dir.exclude.matched(path, is_dir);
+            }
+            saw_git = saw_git || dir.has_git;
+        }
+        let global = if any_git {
+            self.global.matched(path, is_dir)
+        } else {
+            Match::None
+        };
+
+        let matched = zipignore.or(ignore).or(gitignore).or(exclude).or(global);
+        match matched {
+            Match::Ignore(glob) => Match::Ignore(pattern(glob)),
+            Match::Whitelist(glob) => Match::Whitelist(pattern(glob)),
+            Match::None => Match::None,
+        }
+    }
+
+    fn rules(&self, dir: &Path) -> Rc<DirRules> {
+        if let Some(rules) = self.dirs.borrow().get(dir) {
+            return rules.clone();
+        }
+        let matcher = |path: PathBuf| {
+            let mut builder = GitignoreBuilder::new(dir);
+            if path.is_file() {
+                builder.add(path);
+            }
+            builder.build().unwrap_or_else(|_| Gitignore::empty())
+        };
+        let rules = Rc::new(DirRules {
+            zipignore: matcher(dir.join(".zipignore")),
+            ignore: matcher(dir.join(".ignore")),
+            gitignore: if self.config.read_gitignore {
+                matcher(dir.join(".gitignore"))
+            } else {
+                Gitignore::empty()
+            },
+            exclude: matcher(dir.join(".git").join("info").join("exclude")),
+            has_git: dir.join(".git").exists() || dir.join(".jj").exists(),
+        });
+        self.dirs
+            .borrow_mut()
+            .insert(dir.to_path_buf(), rules.clone());
+        rules
+    }
+
+    /// Whether the walk goes into the path, linked directories only when followed
+    fn is_dir(&self, path: &Path) -> bool {
+        if self.config.symlinks == SymlinkMode::Follow {
+            path.is_dir()
+        } else {
+            std::fs::symlink_metadata(path).is_ok_and(|x| x.is_dir())
+        }
+    }
+}
+
+/// A pattern of an ignore file, along with its line in the file.
+///
+/// The matcher doesn't keep line numbers, so the line is found again by
+/// reading the file and looking for the pattern. It is best-effort: a pattern
+/// written more than once is reported at its last line, and none is given if
+/// the file changed since it was read.
+fn pattern(glob: &Glob) -> Rule {
+    let origin = match glob.from() {
+        Some(file) => {
+            // the last line wins when a pattern is repeated
+            let line = std::fs::read_to_string(file).ok().and_then(|content| {
+                let lines: Vec<&str> = content.lines().collect();
+                lines
+                    .iter()
+                    .rposition(|x| x.trim_end() == glob.original() || *x == glob.original())
+                    .map(|x| x + 1)
+            });
+            Origin::File(file.to_path_buf(), line)
+        }
+        None => Origin::Unknown,
+    };
+    Rule::Pattern {
+        glob: glob.original().to_string(),
+        origin,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Input;
+    use crate::explain::{pattern, Explainer, Origin, Rule};
+    use crate::Config;
+    use ignore::gitignore::GitignoreBuilder;
+    use std::fs;
+
+    #[test]
+    fn zipignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(
+            root.join(".zipignore"),
+            "# build\n*.log\ntarget/\n!keep.log\n",
+        )
+        .unwrap();
+        for file in ["a.log", "keep.log", "target/debug/main", "main.rs", ".env"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let config = Config {
+            inputs: vec![Input::new(root)],
+            ..Config::new().unwrap()
+        };
+        let explainer = Explainer::new(&config).unwrap();
+        let zipignore = fs::canonicalize(root).unwrap().join(".zipignore");
+
+        let log = explainer.explain(root.join("a.log")).unwrap();
+        assert!(!log.included);
+        assert_eq!(
+            log.rule,
+            Rule::Pattern {
+                glob: "*.log".to_string(),
+                origin: Origin::File(zipignore.clone(), Some(2)),
+            }
+        );
+
+        let keep = explainer.explain(root.join("keep.log")).unwrap();
+        assert!(keep.included);
+        assert_eq!(
+            keep.rule,
+            Rule::Pattern {
+                glob: "!keep.log".to_string(),
+                origin: Origin::File(zipignore, Some(4)),
+            }
+        );
+
+        let main = explainer.explain(root.join("target/debug/main")).unwrap();
+        assert!(!main.included);
+        assert_eq!(main.parent, Some(root.join("target")));
+
+        let rust = explainer.explain(root.join("main.rs")).unwrap();
+        assert!(rust.included);
+        assert_eq!(rust.rule, Rule::Default);
+
+        let ignored: Vec<_> = explainer.ignored().into_iter().map(|x| x.path).collect();
+        assert_eq!(ignored, vec![root.join("a.log"), root.join("target")]);
+    }
+
+    #[test]
+    fn options() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("src")).unwrap();
+        for file in [".env", "src/main.rs", "src/big.rs", "README.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join("src/big.rs"), "fn main() {}").unwrap();
+        let config = Config {
+            inputs: vec![Input::new(root)],
+            ignore_hidden: true,
+            excludes: vec!["README.md".to_string()],
+            max_file_size: Some(4),
+            ..Config::new().unwrap()
+        };
+        let explainer = Explainer::new(&config).unwrap();
+
+        assert_eq!(
+            explainer.explain(root.join(".env")).unwrap().rule,
+            Rule::Hidden
+        );
+        assert_eq!(
+            explainer.explain(root.join("README.md")).unwrap().rule,
+            Rule::Pattern {
+                glob: "README.md".to_string(),
+                origin: Origin::Exclude,
+            }
+        );
+        assert_eq!(
+            explainer.explain(root.join("src/big.rs")).unwrap().rule,
+            Rule::Size(4)
+        );
+        assert!(
+            explainer
+                .explain(root.join("src/main.rs"))
+                .unwrap()
+                .included
+        );
+        assert!(explainer.explain("/").is_err());
+    }
+
+    #[test]
+    fn pattern_without_file() {
+        let mut builder = GitignoreBuilder::new("/");
+        builder.add_line(None, "*.log").unwrap();
+        let gitignore = builder.build().unwrap();
+        let matched = gitignore.matched("/debug.log", false);
+        let rule = pattern(matched.inner().unwrap());
+        assert_eq!(
+            rule,
+            Rule::Pattern {
+                glob: "*.log".to_string(),
+                origin: Origin::Unknown,
+            }
+        );
+        assert_eq!(rule.to_string(), "by `*.log`");
+    }
+}
@@ -0,0 +1,364 @@
+use crate::error::{Context, Result};
+use crate::fs;
+use filetime::FileTime;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use zip::ZipArchive;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+/// Turn an entry name into a path relative to the extraction target, refusing
+/// absolute names and any `..` component so that no entry can escape the target
+pub fn enclosed_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') {
+        return None;
+    }
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Whether a link at `relative` pointing to `target` stays within the
+/// extraction target, following the `..` components of the target.
+///
+/// The target is resolved without touching the disk, so it may not go through
+/// any of the archive's `links`: `d/l -> ..` then `s -> d/l/d/l/..` would
+/// escape, although both look enclosed. Only its last component may be a link,
+/// which is checked on its own.
+fn enclosed_link(relative: &Path, target: &str, links: &HashSet<PathBuf>) -> bool {
+    let mut resolved = relative.parent().map(Path::to_path_buf).unwrap_or_default();
+    if resolved.ancestors().any(|x| links.contains(x)) {
+        return false;
+    }
+    let components: Vec<Component> = Path::new(target).components().collect();
+    for (i, component) in components.iter().enumerate() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                if i + 1 < components.len() && links.contains(&resolved) {
+                    return false;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    !target.is_empty()
+}
+
+/// Metadata to be restored once everything has been written
+struct Restore {
+    path: PathBuf,
+    mode: Option<u32>,
+    modified: Option<zip::DateTime>,
+}
+
+impl Restore {
+    fn apply(&self) -> Result<()> {
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            let permissions = std::fs::Permissions::from_mode(mode);
+            std::fs::set_permissions(&self.path, permissions).with_path(&self.path)?;
+        }
+        if let Some(modified) = self.modified.and_then(fs::to_system_time) {
+            let mtime = FileTime::from_system_time(modified);
+            filetime::set_file_mtime(&self.path, mtime).with_path(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Extractor {
+    path: PathBuf,
+    archive: ZipArchive<File>,
+    target: PathBuf,
+    dirs: Vec<Restore>,
+    /// Bits of the modes restored, setuid, setgid and sticky only on request
+    mode_mask: u32,
+    /// (path, target) of the links, created once every file has been written
+    links: Vec<(PathBuf, String)>,
+    rejected: Vec<String>,
+    dirs_count: u32,
+    files_count: u32,
+}
+
+impl Extractor {
+    pub fn new<T: AsRef<Path>, U: AsRef<Path>>(archive: T, target: U) -> Result<Extractor> {
+        let archive = archive.as_ref();
+        let file = File::open(archive).with_path(archive)?;
+        Ok(Extractor {
+            path: archive.to_path_buf(),
+            archive: ZipArchive::new(file).with_path(archive)?,
+            target: target.as_ref().to_path_buf(),
+            dirs: vec![],
+            mode_mask: 0o777,
+            links: vec![],
+            rejected: vec![],
+            dirs_count: 0,
+            files_count: 0,
+        })
+    }
+
+    /// Restore the setuid, setgid and sticky bits too, as `unzip -K` does
+    pub fn keep_setuid(mut self, keep: bool) -> Self {
+        self.mode_mask = if keep { 0o7777 } else { 0o777 };
+        self
+    }
+
+    pub fn extract(mut self) -> Result<Self> {
+        std::fs::create_dir_all(&self.target).with_path(&self.target)?;
+        // (relative, target) of every link, checked against each other once all are known
+        let mut links: Vec<(PathBuf, String)> = vec![];
+
+        for i in 0..self.archive.len() {
+            let mut entry = self.archive.by_index(i).with_path(&self.path)?;
+            let relative = match enclosed_path(entry.name()) {
+                Some(relative) => relative,
+                None => {
+                    self.rejected.push(entry.name().to_string());
+                    continue;
+                }
+            };
+            let path = self.target.join(&relative);
+            let restore = Restore {
+                path: path.clone(),
+                mode: entry.unix_mode().map(|x| x & self.mode_mask),
+                modified: entry.last_modified(),
+            };
+
+            if cfg!(unix) && entry.is_symlink() {
+                let mut target = String::new();
+                io::Read::read_to_string(&mut entry, &mut target).with_path(&self.path)?;
+                links.push((relative, target));
+            } else if entry.is_dir() {
+                std::fs::create_dir_all(&path).with_path(&path)?;
+                // directories are restored last, writing their children
+                // would otherwise bump the mtime or hit a read-only mode
+                self.dirs.push(restore);
+                self.dirs_count += 1;
+            } else {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).with_path(parent)?;
+                }
+                let mut file = File::create(&path).with_path(&path)?;
+                io::copy(&mut entry, &mut file).with_path(&path)?;
+                drop(file);
+                restore.apply()?;
+                self.files_count += 1;
+            }
+        }
+
+        // a link leading out of the target would let later writes escape it
+        let relatives: HashSet<PathBuf> = links.iter().map(|x| x.0.clone()).collect();
+        for (relative, target) in links {
+            if enclosed_link(&relative, &target, &relatives) {
+                self.links.push((self.target.join(relative), target));
+            } else {
+                self.rejected
+                    .push(format!("{} -> {}", relative.display(), target));
+            }
+        }
+
+        // nothing is written through a link of the archive
+        #[cfg(unix)]
+        for (path, target) in &self.links {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).with_path(parent)?;
+            }
+            if path.symlink_metadata().is_ok_and(|x| !x.is_dir()) {
+                std::fs::remove_file(path).with_path(path)?;
+            }
+            std::os::unix::fs::symlink(target, path).with_path(path)?;
+        }
+
+        // deepest directories first, so a parent is never touched after its children
+        self.dirs.sort_by(|a, b| b.path.cmp(&a.path));
+        for dir in &self.dirs {
+            dir.apply()?;
+        }
+        Ok(self)
+    }
+
+    pub fn finish(self) {
+        for name in &self.rejected {
+            println!("REJECTED: {}", name);
+        }
+        print!(
+            "Extract Complete: {} directory(s), {} file(s) in total",
+            self.dirs_count, self.files_count
+        );
+        if !self.links.is_empty() {
+            print!(", {} symlink(s)", self.links.len());
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::extract::{enclosed_link, enclosed_path, Extractor};
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn plain_names() {
+        assert_eq!(enclosed_path("a.txt"), Some(PathBuf::from("a.txt")));
+        assert_eq!(enclosed_path("dir/"), Some(PathBuf::from("dir")));
+        assert_eq!(
+            enclosed_path("./dir/a.txt"),
+            Some(PathBuf::from("dir/a.txt"))
+        );
+    }
+
+    #[test]
+    fn parent_components() {
+        assert_eq!(enclosed_path("../a.txt"), None);
+        assert_eq!(enclosed_path("dir/../../a.txt"), None);
+        assert_eq!(enclosed_path("dir/../a.txt"), None);
+    }
+
+    #[test]
+    fn absolute_names() {
+        assert_eq!(enclosed_path("/etc/passwd"), None);
+    }
+
+    #[test]
+    fn empty_names() {
+        assert_eq!(enclosed_path(""), None);
+        assert_eq!(enclosed_path("./"), None);
+        assert_eq!(enclosed_path("a\0b"), None);
+    }
+
+    #[test]
+    fn link_targets() {
+        let none = HashSet::new();
+        assert!(enclosed_link(Path::new("a"), "b", &none));
+        assert!(enclosed_link(Path::new("dir/a"), "../b", &none));
+        assert!(enclosed_link(Path::new("dir/a"), "./sub/../../b", &none));
+        assert!(!enclosed_link(Path::new("a"), "../b", &none));
+        assert!(!enclosed_link(Path::new("dir/a"), "sub/../../../b", &none));
+        assert!(!enclosed_link(Path::new("a"), "/etc/passwd", &none));
+        assert!(!enclosed_link(Path::new("a"), "", &none));
+
+        // each enclosed on its own, escaping once chained
+        let links = HashSet::from([PathBuf::from("d/d2"), PathBuf::from("s")]);
+        assert!(enclosed_link(Path::new("d/d2"), "..", &links));
+        assert!(!enclosed_link(Path::new("s"), "d/d2/d/d2/..", &links));
+        assert!(!enclosed_link(Path::new("s"), "d/d2/x", &links));
+        // pointing at a link is fine, that link is checked on its own
+        assert!(enclosed_link(Path::new("s"), "d/d2", &links));
+        // nor may a link be created through another one
+        assert!(!enclosed_link(Path::new("d/d2/t"), "x", &links));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restore() {
+        use crate::config::Input;
+        use crate::fs::test::LOCAL_OFFSET;
+        use crate::fs::Snapshot;
+        use crate::zipper::Zipper;
+        use crate::Config;
+        use filetime::FileTime;
+        use std::os::unix::fs::PermissionsExt;
+        use time::UtcOffset;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        std::fs::create_dir_all(root.join("ro/inner")).unwrap();
+        std::fs::write(root.join("run.sh"), "#!/bin/sh").unwrap();
+        std::fs::write(root.join("ro/inner/data.txt"), "data").unwrap();
+        // zip timestamps have a two seconds resolution
+        let expected = [
+            ("run.sh", 0o750, 1_700_000_000),
+            ("ro/inner/data.txt", 0o600, 1_700_000_002),
+            ("ro/inner", 0o700, 1_700_000_004),
+            ("ro", 0o555, 1_700_000_006),
+        ];
+        for (path, mode, mtime) in expected {
+            let path = root.join(path);
+            let mtime = FileTime::from_unix_time(mtime, 0);
+            filetime::set_file_mtime(&path, mtime).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        LOCAL_OFFSET.set(Some(UtcOffset::from_hms(5, 30, 0).unwrap()));
+        let config = Config {
+            inputs: vec![Input::new(&root)],
+            output: dir.path().join("src.zip"),
+            quiet: true,
+            ..Config::new().unwrap()
+        };
+        Zipper::new(&config, &Snapshot::new(&config))
+            .and_then(Zipper::build_dir)
+            .and_then(Zipper::build_files)
+            .and_then(Zipper::finish)
+            .unwrap();
+        let target = dir.path().join("out");
+        Extractor::new(&config.output, &target)
+            .and_then(Extractor::extract)
+            .unwrap();
+        LOCAL_OFFSET.set(None);
+
+        // directories are restored after their children, even a read-only one
+        for (path, mode, mtime) in expected {
+            let metadata = std::fs::metadata(target.join(path)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, mode, "{}", path);
+            let modified = FileTime::from_last_modification_time(&metadata);
+            assert_eq!(modified.unix_seconds(), mtime, "{}", path);
+        }
+        for ro in [root.join("ro"), target.join("ro")] {
+            std::fs::set_permissions(ro, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_setuid() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+        use zip::write::FileOptions;
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("setuid.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        let option: FileOptions<()> = FileOptions::default().unix_permissions(0o755);
+        writer.start_file("run", option).unwrap();
+        writer.write_all(b"#!/bin/sh").unwrap();
+        writer.finish().unwrap();
+        // the writer drops the setuid bit, as other tools don't, set it in the
+        // external attributes of the central directory
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let header = bytes.windows(4).position(|x| x == b"PK\x01\x02").unwrap();
+        let attributes = header + 38..header + 42;
+        let mode = u32::from_le_bytes(bytes[attributes.clone()].try_into().unwrap());
+        bytes[attributes].copy_from_slice(&(mode | 0o4000 << 16).to_le_bytes());
+        std::fs::write(&archive, bytes).unwrap();
+
+        let mode = |keep: bool| {
+            let target = dir.path().join(format!("{}", keep));
+            Extractor::new(&archive, &target)
+                .map(|x| x.keep_setuid(keep))
+                .and_then(Extractor::extract)
+                .unwrap();
+            let metadata = std::fs::metadata(target.join("run")).unwrap();
+            metadata.permissions().mode() & 0o7777
+        };
+        assert_eq!(mode(false), 0o755);
+        assert_eq!(mode(true), 0o4755);
+    }
+}
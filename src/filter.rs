@@ -0,0 +1,158 @@
+use crate::error::{Error, Result};
+use crate::fs::local_offset;
+use ignore::types::{Types, TypesBuilder};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+/// A size in bytes with an optional binary unit, e.g. `512`, `64K`, `50M` or `2GiB`
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {}", value))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(format!("invalid size unit {} in {}", unit, value)),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or(format!("size {} is too large", value))
+}
+
+/// Either a date, `2026-01-01` at midnight local time, or an age relative to
+/// now in seconds, minutes, hours, days or weeks, e.g. `30d`
+pub fn parse_time(value: &str) -> std::result::Result<SystemTime, String> {
+    parse_time_at(value, SystemTime::now())
+}
+
+fn parse_time_at(value: &str, now: SystemTime) -> std::result::Result<SystemTime, String> {
+    let value = value.trim();
+    if let Some(date) = parse_date(value) {
+        let midnight = PrimitiveDateTime::new(date, Time::MIDNIGHT);
+        return Ok(midnight.assume_offset(local_offset()).into());
+    }
+
+    let split = value
+        .find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid date or age {}, e.g. 2026-01-01 or 30d", value))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid age unit {} in {}, expected s, m, h, d or w",
+                unit, value
+            ))
+        }
+    };
+    number
+        .checked_mul(seconds)
+        .and_then(|x| now.checked_sub(Duration::from_secs(x)))
+        .ok_or(format!("age {} is too large", value))
+}
+
+fn parse_date(value: &str) -> Option<Date> {
+    let mut parts = value.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month: u8 = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()
+}
+
+/// Matcher for the file types known to ripgrep, e.g. `rust` or `md`
+pub fn types(names: &[String]) -> Result<Types> {
+    let mut builder = TypesBuilder::new();
+    builder.add_defaults();
+    for name in names {
+        builder.select(name);
+    }
+    builder
+        .build()
+        .map_err(|err| Error::input(format!("invalid file type: {}", err)))
+}
+
+/// Whether a file was modified within the bounds, directories always pass
+pub fn modified_within(
+    path: &Path,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+) -> bool {
+    // unreadable metadata is reported later, when the file is zipped
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return true;
+    };
+    if metadata.is_dir() {
+        return true;
+    }
+    let Ok(modified) = metadata.modified() else {
+        return true;
+    };
+    newer_than.is_none_or(|x| modified > x) && older_than.is_none_or(|x| modified < x)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::filter::{parse_size, parse_time_at, types};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("50M"), Ok(50 * 1024 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1 mb"), Ok(1024 * 1024));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("5X").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn age() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * 24 * 60 * 60);
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(parse_time_at("30d", now), Ok(now - day * 30));
+        assert_eq!(parse_time_at("2w", now), Ok(now - day * 14));
+        assert_eq!(
+            parse_time_at("90m", now),
+            Ok(now - Duration::from_secs(90 * 60))
+        );
+        assert!(parse_time_at("30y", now).is_err());
+        assert!(parse_time_at("d", now).is_err());
+    }
+
+    #[test]
+    fn date() {
+        let now = SystemTime::now();
+        let date = parse_time_at("2026-01-01", now).unwrap();
+        let since_epoch = date.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        // 2026-01-01T00:00:00Z, give or take the local offset
+        let utc = 1_767_225_600;
+        assert!(since_epoch.as_secs().abs_diff(utc) <= 14 * 60 * 60);
+        assert!(parse_time_at("2026-13-01", now).is_err());
+    }
+
+    #[test]
+    fn file_types() {
+        let types = types(&["rust".to_string()]).unwrap();
+        assert!(types.matched("main.rs", false).is_whitelist());
+        assert!(types.matched("README.md", false).is_ignore());
+        assert!(crate::filter::types(&["no-such-type".to_string()]).is_err());
+    }
+}
@@ -1,100 +1,668 @@
+use crate::config::Input;
+use crate::error::{Context, Error, Result};
+use crate::explain::{Explainer, Explanation};
+use crate::filter;
 use crate::Config;
-use ignore::{Walk, WalkBuilder};
+use clap::ValueEnum;
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{
+    DirEntry, ParallelVisitor, ParallelVisitorBuilder, WalkBuilder, WalkParallel, WalkState,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
 
-use time::{OffsetDateTime, UtcOffset};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
 
-pub fn walker<T: AsRef<Path>>(directory: T, config: Option<&Config>) -> Walk {
-    let mut builder = WalkBuilder::new(directory);
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+/// What to do with the symbolic links met during the walk
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkMode {
+    /// Zip what the link points to, walking into linked directories
+    Follow,
+    /// Store the link itself, as a unix symlink entry
+    Preserve,
+    /// Leave links out
+    Skip,
+}
+
+/// A file which is neither a regular file, a directory nor a symlink, and
+/// can't be zipped. Opening a FIFO would block until something writes to it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Special {
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    Unknown,
+}
+
+impl Special {
+    /// `None` for regular files, directories and symlinks
+    pub fn of(file_type: fs::FileType) -> Option<Special> {
+        if file_type.is_file() || file_type.is_dir() || file_type.is_symlink() {
+            return None;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                return Some(Special::Fifo);
+            } else if file_type.is_socket() {
+                return Some(Special::Socket);
+            } else if file_type.is_block_device() {
+                return Some(Special::BlockDevice);
+            } else if file_type.is_char_device() {
+                return Some(Special::CharDevice);
+            }
+        }
+        Some(Special::Unknown)
+    }
+}
+
+impl Display for Special {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Special::Fifo => "fifo",
+            Special::Socket => "socket",
+            Special::BlockDevice => "block device",
+            Special::CharDevice => "character device",
+            Special::Unknown => "special file",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub fn walker<T: AsRef<Path>>(directory: T, config: Option<&Config>) -> WalkParallel {
+    let mut builder = WalkBuilder::new(&directory);
     builder.add_custom_ignore_filename(".zipignore");
     if let Some(config) = config {
         builder
             .max_depth(config.depth)
             .git_ignore(config.read_gitignore)
-            .hidden(config.ignore_hidden);
+            .hidden(config.ignore_hidden)
+            // a link back to an ancestor is reported as a walk error
+            .follow_links(config.symlinks == SymlinkMode::Follow)
+            .threads(config.jobs.get());
+        // the patterns and types have been checked when the config was built
+        if let Ok(overrides) = overrides(&directory, config) {
+            builder.overrides(overrides);
+        }
+        if let Ok(types) = filter::types(&config.types) {
+            builder.types(types);
+        }
+        builder.max_filesize(config.max_file_size);
+        let (newer_than, older_than) = (config.newer_than, config.older_than);
+        let skip_links = config.symlinks == SymlinkMode::Skip;
+        if newer_than.is_some() || older_than.is_some() || skip_links {
+            builder.filter_entry(move |x| {
+                !(skip_links && x.path_is_symlink())
+                    && filter::modified_within(x.path(), newer_than, older_than)
+            });
+        }
     }
-    builder.build()
+    builder.build_parallel()
+}
+
+/// What an entry of the walk is, links are only met when not followed
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Special(Special),
+}
+
+/// An entry met by the walk
+#[derive(Debug)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// 0 for the input itself
+    pub depth: usize,
+    pub kind: Kind,
+    /// Read by the walker, of the target when links are followed
+    pub metadata: std::result::Result<fs::Metadata, ignore::Error>,
 }
 
-pub fn parent_dir_name<T: AsRef<Path>>(path: T) -> String {
-    let absolute = std::fs::canonicalize(path).unwrap();
-    let parent_dir = absolute.strip_prefix(absolute.parent().unwrap()).unwrap();
-    parent_dir.to_str().unwrap().to_string()
-    // parent_dir.to_path_buf()
+impl From<DirEntry> for Entry {
+    fn from(entry: DirEntry) -> Self {
+        let kind = match entry.file_type() {
+            Some(x) if x.is_symlink() => Kind::Symlink,
+            Some(x) if x.is_dir() => Kind::Dir,
+            Some(x) => Special::of(x).map_or(Kind::File, Kind::Special),
+            // stdin
+            None => Kind::File,
+        };
+        Entry {
+            depth: entry.depth(),
+            metadata: entry.metadata(),
+            path: entry.into_path(),
+            kind,
+        }
+    }
 }
 
-pub fn last_modified<T: AsRef<Path>>(path: T) -> zip::DateTime {
-    let meta = fs::metadata(path).unwrap();
-    let system_time = meta.modified().unwrap();
+/// Everything met while walking the inputs, shared by the tree view and the
+/// zipper so that the tree shown is exactly what gets zipped
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// The entries of each input sorted by path, each directory before its
+    /// children, whatever order the walkers met them in
+    pub inputs: Vec<(Input, Vec<Entry>)>,
+    /// Entries the walk couldn't read, e.g. a directory without permission
+    pub errors: Vec<ignore::Error>,
+    /// What was left out and why, only with [`Snapshot::with_ignored`].
+    /// Unlike the entries, these come from a second walk.
+    pub ignored: Vec<Explanation>,
+}
+
+impl Snapshot {
+    /// Walk the inputs on `config.jobs` threads
+    pub fn new(config: &Config) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for input in &config.inputs {
+            let shared = Mutex::new((vec![], vec![]));
+            walker(&input.path, Some(config)).visit(&mut Collect(&shared));
+            let (mut entries, errors) = shared.into_inner().unwrap();
+            entries.sort_by(|a: &Entry, b: &Entry| a.path.cmp(&b.path));
+            snapshot.inputs.push((input.clone(), entries));
+            snapshot.errors.extend(errors);
+        }
+        snapshot.errors.sort_by_key(|x| x.to_string());
+        snapshot
+    }
+
+    /// Trace the rules leaving paths out, next to the entries walked.
+    ///
+    /// The walker never reports what it leaves out, so the left out paths are
+    /// found by [`Explainer::ignored`], a sequential walk of its own after this
+    /// one. A path created or removed in between may be missing from them or
+    /// listed although it is gone, but a walked entry is never listed as left out.
+    pub fn with_ignored(mut self, config: &Config) -> Result<Snapshot> {
+        let walked: HashSet<&Path> = self
+            .inputs
+            .iter()
+            .flat_map(|(_, entries)| entries.iter().map(|x| x.path.as_path()))
+            .collect();
+        // the second walk may have met a path the first one included
+        let ignored = Explainer::new(config)?
+            .ignored()
+            .into_iter()
+            .filter(|x| !walked.contains(x.path.as_path()))
+            .collect();
+        self.ignored = ignored;
+        Ok(self)
+    }
+}
+
+type Collected = Mutex<(Vec<Entry>, Vec<ignore::Error>)>;
+
+/// Builds a visitor for each walker thread
+struct Collect<'s>(&'s Collected);
+
+impl<'s> ParallelVisitorBuilder<'s> for Collect<'s> {
+    fn build(&mut self) -> Box<dyn ParallelVisitor + 's> {
+        Box::new(Visitor {
+            entries: vec![],
+            errors: vec![],
+            shared: self.0,
+        })
+    }
+}
+
+/// Collects the entries of one thread, handing them over when the walk is done
+struct Visitor<'s> {
+    entries: Vec<Entry>,
+    errors: Vec<ignore::Error>,
+    shared: &'s Collected,
+}
+
+impl ParallelVisitor for Visitor<'_> {
+    fn visit(&mut self, entry: std::result::Result<DirEntry, ignore::Error>) -> WalkState {
+        match entry {
+            Ok(entry) => self.entries.push(entry.into()),
+            Err(err) => self.errors.push(err),
+        }
+        WalkState::Continue
+    }
+}
+
+impl Drop for Visitor<'_> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.0.append(&mut self.entries);
+        shared.1.append(&mut self.errors);
+    }
+}
+
+/// Include and exclude patterns in .gitignore syntax, which take precedence
+/// over the .zipignore and .gitignore files. Once there is an include pattern,
+/// files matching none of them are left out. An exclude pattern wins over an
+/// include pattern matching the same path.
+pub fn overrides<T: AsRef<Path>>(directory: T, config: &Config) -> Result<Override> {
+    let invalid = |err: ignore::Error| Error::input(format!("invalid pattern: {}", err));
+    let mut builder = OverrideBuilder::new(directory);
+    for pattern in &config.includes {
+        builder.add(pattern).map_err(invalid)?;
+    }
+    for pattern in &config.excludes {
+        builder.add(&format!("!{}", pattern)).map_err(invalid)?;
+    }
+    builder.build().map_err(invalid)
+}
+
+pub fn parent_dir_name<T: AsRef<Path>>(path: T) -> Result<String> {
+    let path = path.as_ref();
+    let absolute = std::fs::canonicalize(path).with_path(path)?;
+    let parent_dir = absolute.file_name().unwrap_or(absolute.as_os_str());
+    parent_dir
+        .to_str()
+        .map(String::from)
+        .ok_or_else(|| Error::input(format!("{} is not valid UTF-8", absolute.display())))
+}
+
+/// The local offset can only be queried safely while the process is single
+/// threaded, so it is looked up once and cached, falling back to UTC
+pub fn local_offset() -> UtcOffset {
+    #[cfg(test)]
+    if let Some(offset) = test::LOCAL_OFFSET.get() {
+        return offset;
+    }
+    static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+    *LOCAL_OFFSET.get_or_init(|| UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC))
+}
+
+/// Zip timestamps have no timezone, they are stored in local time. Those
+/// out of the range a zip entry can hold are clamped to 1980-01-01.
+pub fn last_modified(metadata: &fs::Metadata) -> io::Result<zip::DateTime> {
+    let system_time = metadata.modified()?;
     let offset_time = OffsetDateTime::from(system_time);
-    let local_offset = UtcOffset::current_local_offset().unwrap();
-    let local_time = offset_time.to_offset(local_offset);
-    zip::DateTime::try_from(local_time).unwrap()
+    let local_time = offset_time.to_offset(local_offset());
+    Ok(zip::DateTime::try_from(local_time).unwrap_or_default())
+}
+
+/// Earliest timestamp a zip entry can hold, 1980-01-01 00:00:00 UTC
+const ZIP_EPOCH: i64 = 315_532_800;
+
+/// Timestamp and unix mode stored for an entry
+pub struct Stamp {
+    pub modified: zip::DateTime,
+    /// Seconds since the unix epoch
+    pub mtime: i64,
+    pub mode: u32,
+}
+
+impl Stamp {
+    pub fn new(metadata: &fs::Metadata) -> io::Result<Stamp> {
+        let mtime = OffsetDateTime::from(metadata.modified()?).unix_timestamp();
+        #[cfg(unix)]
+        let mode = metadata.permissions().mode();
+        #[cfg(not(unix))]
+        let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+        Ok(Stamp {
+            modified: last_modified(metadata)?,
+            mtime,
+            mode,
+        })
+    }
+
+    /// A stamp which doesn't depend on the local timezone, the checkout time or
+    /// the umask: the mtime is clamped to `epoch` (the zip epoch if none) and
+    /// only the executable bit of the mode is kept. Generated entries have no
+    /// `metadata` and are stamped with `epoch` directly.
+    pub fn reproducible(metadata: Option<&fs::Metadata>, epoch: Option<i64>) -> io::Result<Stamp> {
+        let epoch = epoch.unwrap_or(ZIP_EPOCH);
+        let mtime = match metadata {
+            Some(metadata) => {
+                let modified = OffsetDateTime::from(metadata.modified()?);
+                modified.unix_timestamp().min(epoch)
+            }
+            None => epoch,
+        }
+        .max(ZIP_EPOCH);
+        let utc = OffsetDateTime::from_unix_timestamp(mtime).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+        #[cfg(unix)]
+        let executable = metadata.is_some_and(|x| x.permissions().mode() & 0o111 != 0);
+        #[cfg(not(unix))]
+        let executable = false;
+        let mode = if metadata.is_some_and(|x| x.is_dir()) || executable {
+            0o755
+        } else {
+            0o644
+        };
+
+        Ok(Stamp {
+            modified: zip::DateTime::try_from(utc).unwrap_or_default(),
+            mtime,
+            mode,
+        })
+    }
+}
+
+/// Inverse of [`last_modified`], the zip timestamp is interpreted in the local
+/// timezone. Returns `None` for a timestamp which isn't a valid date.
+pub fn to_system_time(datetime: zip::DateTime) -> Option<SystemTime> {
+    let local_time = PrimitiveDateTime::try_from(datetime).ok()?;
+    Some(local_time.assume_offset(local_offset()).into())
+}
+
+/// The path with its parent resolved, which needn't exist, as is if that fails
+fn canonical(path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match (fs::canonicalize(parent), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
 }
 
 #[derive(Debug)]
+/// The files and directories of all the inputs, each paired with its path
+/// inside the archive
 pub struct FileCollector {
-    base_dir: PathBuf,
-    files: Vec<PathBuf>,
-    dirs: Vec<PathBuf>,
+    files: Vec<(PathBuf, PathBuf)>,
+    dirs: Vec<(PathBuf, PathBuf)>,
+    /// Links stored as such, with `--symlinks preserve`
+    links: Vec<(PathBuf, PathBuf)>,
+    /// FIFOs, sockets and devices, left out of the archive
+    special: Vec<(PathBuf, Special)>,
+    /// Entries the walk couldn't read, e.g. a directory without permission
+    errors: Vec<ignore::Error>,
+    /// As read by the walk, by absolute path
+    metadata: HashMap<PathBuf, std::result::Result<fs::Metadata, ignore::Error>>,
 }
 
 impl FileCollector {
-    pub fn new<T: AsRef<Path>>(directory: T, config: Option<&Config>) -> FileCollector {
+    pub fn new(snapshot: &Snapshot, config: &Config) -> Result<FileCollector> {
         FileCollector {
-            base_dir: PathBuf::from(directory.as_ref()),
             files: vec![],
             dirs: vec![],
+            links: vec![],
+            special: vec![],
+            errors: snapshot.errors.clone(),
+            metadata: HashMap::new(),
         }
-        .prepare(directory, config)
-    }
-
-    fn prepare<T: AsRef<Path>>(mut self, directory: T, config: Option<&Config>) -> Self {
-        for result in walker(directory, config) {
-            match result {
-                Ok(s) => {
-                    if s.path().is_file() {
-                        self.files.push(s.into_path());
-                    } else if s.path().is_dir() {
-                        self.dirs.push(s.into_path());
-                    } else {
-                        unreachable!()
-                    }
+        .prepare(snapshot, config)
+    }
+
+    fn prepare(mut self, snapshot: &Snapshot, config: &Config) -> Result<Self> {
+        // the default output is inside the default input, where the archive of
+        // a previous run would otherwise be zipped while being overwritten
+        let outputs: Vec<PathBuf> = config.outputs().iter().map(|x| canonical(x)).collect();
+        for (input, entries) in &snapshot.inputs {
+            for entry in entries {
+                let path = entry.path.as_path();
+                if outputs
+                    .iter()
+                    .any(|x| x.file_name() == path.file_name() && *x == canonical(path))
+                {
+                    continue;
                 }
-                Err(_) => {}
+                // a file given as input is at depth 0 and keeps its name
+                let relative = match path.strip_prefix(&input.path) {
+                    Ok(relative) if entry.depth > 0 => relative,
+                    _ => path.file_name().map(Path::new).unwrap_or(Path::new("")),
+                };
+                let relative = input.prefix.join(relative);
+                let absolute = entry.path.clone();
+                match entry.kind {
+                    Kind::File => self.files.push((absolute, relative)),
+                    // the input itself is the root of the archive
+                    Kind::Dir if entry.depth > 0 => self.dirs.push((absolute, relative)),
+                    Kind::Dir => {}
+                    Kind::Symlink => self.links.push((absolute, relative)),
+                    Kind::Special(kind) => self.special.push((absolute, kind)),
+                }
+                self.metadata
+                    .insert(entry.path.clone(), entry.metadata.clone());
+            }
+        }
+
+        // the snapshot is sorted input by input, inputs in the order given
+        if config.reproducible {
+            self.files.sort_by(|a, b| a.1.cmp(&b.1));
+            self.dirs.sort_by(|a, b| a.1.cmp(&b.1));
+            self.links.sort_by(|a, b| a.1.cmp(&b.1));
+        }
+
+        // inputs may share directories, but not files
+        let mut seen: HashMap<&Path, &Path> = HashMap::new();
+        for (absolute, relative) in self.files.iter().chain(&self.links) {
+            if let Some(first) = seen.insert(relative, absolute) {
+                return Err(Error::input(format!(
+                    "{} and {} would both be stored as {}",
+                    first.display(),
+                    absolute.display(),
+                    relative.display()
+                )));
             }
         }
-        self
+        let mut seen = HashSet::new();
+        self.dirs
+            .retain(|(_, relative)| seen.insert(relative.clone()));
+        Ok(self)
+    }
+
+    /// Take the walk errors, leaving none behind
+    pub fn take_errors(&mut self) -> Vec<ignore::Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Metadata of a collected path, as the walk read it
+    pub fn metadata(&self, absolute: &Path) -> Result<&fs::Metadata> {
+        match self.metadata.get(absolute) {
+            Some(Ok(metadata)) => Ok(metadata),
+            Some(Err(err)) => Err(err.clone().into()),
+            None => Err(Error::input(format!(
+                "{} wasn't walked",
+                absolute.display()
+            ))),
+        }
     }
 
     /// return (absolute, relative)
     pub fn dirs(&self) -> Vec<(&Path, &Path)> {
-        let dirs: Vec<(&Path, &Path)> = self
-            .dirs
+        self.dirs
             .iter()
-            .map(|x| {
-                (
-                    x.as_path(),
-                    x.as_path().strip_prefix(&self.base_dir).unwrap(),
-                )
-            })
-            .collect();
-        dirs
+            .map(|(absolute, relative)| (absolute.as_path(), relative.as_path()))
+            .collect()
     }
 
     /// return (absolute, relative)
     pub fn files(&self) -> Vec<(&Path, &Path)> {
-        let files: Vec<(&Path, &Path)> = self
-            .files
+        self.files
             .iter()
-            .map(|x| {
-                (
-                    x.as_path(),
-                    x.as_path().strip_prefix(&self.base_dir).unwrap(),
-                )
-            })
-            .collect();
-        files
+            .map(|(absolute, relative)| (absolute.as_path(), relative.as_path()))
+            .collect()
+    }
+
+    /// return (absolute, kind)
+    pub fn special(&self) -> Vec<(&Path, Special)> {
+        self.special
+            .iter()
+            .map(|(absolute, kind)| (absolute.as_path(), *kind))
+            .collect()
+    }
+
+    /// return (absolute, relative)
+    pub fn links(&self) -> Vec<(&Path, &Path)> {
+        self.links
+            .iter()
+            .map(|(absolute, relative)| (absolute.as_path(), relative.as_path()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::config::Input;
+    use crate::fs::{overrides, FileCollector, Snapshot, Special, Stamp, SymlinkMode, ZIP_EPOCH};
+    use crate::Config;
+    use filetime::FileTime;
+    use std::cell::Cell;
+    use std::path::Path;
+    use time::UtcOffset;
+
+    thread_local! {
+        /// Stands for the local offset of the machine, on this thread only
+        pub static LOCAL_OFFSET: Cell<Option<UtcOffset>> = const { Cell::new(None) };
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let config = Config {
+            includes: vec!["*.rs".to_string()],
+            excludes: vec!["generated.rs".to_string()],
+            ..Config::new().unwrap()
+        };
+        let overrides = overrides("/src", &config).unwrap();
+        assert!(overrides.matched("/src/main.rs", false).is_whitelist());
+        assert!(overrides.matched("/src/generated.rs", false).is_ignore());
+        assert!(overrides.matched("/src/README.md", false).is_ignore());
+        // directories are still walked into
+        assert!(!overrides.matched("/src/sub", true).is_ignore());
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let config = Config {
+            excludes: vec!["a{".to_string()],
+            ..Config::new().unwrap()
+        };
+        assert!(overrides("/src", &config).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("lib")).unwrap();
+        std::fs::write(root.join("lib/real.txt"), "").unwrap();
+        std::os::unix::fs::symlink("lib/real.txt", root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("broken")).unwrap();
+
+        let collect = |symlinks| {
+            let config = Config {
+                inputs: vec![Input::new(root)],
+                symlinks,
+                reproducible: true,
+                ..Config::new().unwrap()
+            };
+            FileCollector::new(&Snapshot::new(&config), &config).unwrap()
+        };
+        let relative = |x: Vec<(&Path, &Path)>| -> Vec<String> {
+            x.iter().map(|x| x.1.display().to_string()).collect()
+        };
+
+        let mut followed = collect(SymlinkMode::Follow);
+        assert_eq!(relative(followed.files()), ["lib/real.txt", "link.txt"]);
+        assert_eq!(followed.take_errors().len(), 1);
+
+        let preserved = collect(SymlinkMode::Preserve);
+        assert_eq!(relative(preserved.files()), ["lib/real.txt"]);
+        assert_eq!(relative(preserved.links()), ["broken", "link.txt"]);
+
+        let skipped = collect(SymlinkMode::Skip);
+        assert_eq!(relative(skipped.files()), ["lib/real.txt"]);
+        assert!(skipped.links().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("socket");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        std::fs::write(dir.path().join("file"), "").unwrap();
+
+        let config = Config {
+            inputs: vec![Input::new(dir.path())],
+            ..Config::new().unwrap()
+        };
+        let collector = FileCollector::new(&Snapshot::new(&config), &config).unwrap();
+        assert_eq!(collector.files().len(), 1);
+        assert_eq!(collector.special(), [(socket.as_path(), Special::Socket)]);
+    }
+
+    #[test]
+    fn snapshot_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "content").unwrap();
+
+        let config = Config {
+            inputs: vec![Input::new(dir.path())],
+            ..Config::new().unwrap()
+        };
+        let snapshot = Snapshot::new(&config);
+        std::fs::remove_file(&file).unwrap();
+        // the archive is made from what was walked, not from a second look
+        let collector = FileCollector::new(&snapshot, &config).unwrap();
+        assert_eq!(collector.metadata(&file).unwrap().len(), 7);
+        assert!(collector.metadata(&dir.path().join("other")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reproducible_stamp() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let stamp = |mtime: i64, mode: u32, epoch: Option<i64>| {
+            filetime::set_file_mtime(&file, FileTime::from_unix_time(mtime, 0)).unwrap();
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(mode)).unwrap();
+            Stamp::reproducible(Some(&std::fs::metadata(&file).unwrap()), epoch).unwrap()
+        };
+
+        let epoch = Some(1_700_000_000);
+        // clamped to the epoch, older mtimes are kept
+        assert_eq!(stamp(1_800_000_000, 0o644, epoch).mtime, 1_700_000_000);
+        assert_eq!(stamp(1_600_000_000, 0o644, epoch).mtime, 1_600_000_000);
+        // and never before the zip epoch, which is the default
+        assert_eq!(stamp(1_600_000_000, 0o644, None).mtime, ZIP_EPOCH);
+        assert_eq!(stamp(1_600_000_000, 0o644, Some(0)).mtime, ZIP_EPOCH);
+        assert_eq!(
+            Stamp::reproducible(None, epoch).unwrap().mtime,
+            1_700_000_000
+        );
+
+        // only the executable bit is kept
+        assert_eq!(stamp(0, 0o600, epoch).mode, 0o644);
+        assert_eq!(stamp(0, 0o664, epoch).mode, 0o644);
+        assert_eq!(stamp(0, 0o700, epoch).mode, 0o755);
+        assert_eq!(stamp(0, 0o4775, epoch).mode, 0o755);
+        let metadata = std::fs::metadata(dir.path()).unwrap();
+        assert_eq!(
+            Stamp::reproducible(Some(&metadata), epoch).unwrap().mode,
+            0o755
+        );
+
+        // stored in UTC, whatever the local offset
+        LOCAL_OFFSET.set(Some(UtcOffset::from_hms(9, 0, 0).unwrap()));
+        let modified = stamp(1_800_000_000, 0o644, epoch).modified;
+        LOCAL_OFFSET.set(None);
+        // 2023-11-14T22:13:20Z
+        assert_eq!(
+            (
+                modified.year(),
+                modified.month(),
+                modified.day(),
+                modified.hour()
+            ),
+            (2023, 11, 14, 22)
+        );
     }
 }
@@ -0,0 +1,248 @@
+use crate::error::{Context, Result};
+use crate::tree_view::{TreeDepth, TreeParams, TreeTrunk};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Central directory record of a single entry
+pub struct ArchiveEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub compression: zip::CompressionMethod,
+    pub modified: Option<zip::DateTime>,
+}
+
+/// A node in the hierarchy rebuilt from the flat entry names, directories
+/// without an entry of their own (e.g. `a/` for `a/b.txt`) have no `entry`
+#[derive(Default)]
+struct Node {
+    entry: Option<ArchiveEntry>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, entry: ArchiveEntry) {
+        let mut node = self;
+        for part in entry.name.split('/').filter(|part| !part.is_empty()) {
+            node = node.children.entry(part.to_string()).or_default();
+        }
+        node.entry = Some(entry);
+    }
+}
+
+/// Width of the size columns in the long format
+#[derive(Copy, Clone)]
+struct Widths {
+    size: usize,
+    compressed_size: usize,
+}
+
+impl Widths {
+    fn format(
+        &self,
+        size: &str,
+        compressed_size: &str,
+        compression: &str,
+        modified: &str,
+    ) -> String {
+        format!(
+            "{:>w1$} {:>w2$} {:<9} {:<19} ",
+            size,
+            compressed_size,
+            compression,
+            modified,
+            w1 = self.size,
+            w2 = self.compressed_size,
+        )
+    }
+}
+
+pub struct Listing {
+    name: String,
+    root: Node,
+    long: bool,
+    size: u64,
+    compressed_size: u64,
+}
+
+impl Listing {
+    pub fn new<T: AsRef<Path>>(path: T, long: bool) -> Result<Listing> {
+        let path = path.as_ref();
+        let file = File::open(path).with_path(path)?;
+        let mut archive = ZipArchive::new(file).with_path(path)?;
+
+        let mut entries = vec![];
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i).with_path(path)?;
+            entries.push(ArchiveEntry {
+                name: entry.name().to_string(),
+                is_dir: entry.is_dir(),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+                compression: entry.compression(),
+                modified: entry.last_modified(),
+            });
+        }
+        Ok(Listing::from_entries(
+            path.display().to_string(),
+            entries,
+            long,
+        ))
+    }
+
+    pub fn from_entries(name: String, entries: Vec<ArchiveEntry>, long: bool) -> Listing {
+        let mut listing = Listing {
+            name,
+            root: Node::default(),
+            long,
+            size: 0,
+            compressed_size: 0,
+        };
+        for entry in entries {
+            listing.size += entry.size;
+            listing.compressed_size += entry.compressed_size;
+            listing.root.insert(entry);
+        }
+        listing
+    }
+
+    pub fn display(&self) {
+        println!("{}", self);
+    }
+
+    fn columns(&self, entry: Option<&ArchiveEntry>, widths: Widths) -> String {
+        if !self.long {
+            return String::new();
+        }
+        let (size, compressed_size, compression) = match entry {
+            Some(entry) if !entry.is_dir => (
+                entry.size.to_string(),
+                entry.compressed_size.to_string(),
+                entry.compression.to_string(),
+            ),
+            _ => ("-".to_string(), "-".to_string(), String::new()),
+        };
+        let modified = entry
+            .and_then(|entry| entry.modified)
+            .map(|modified| modified.to_string())
+            .unwrap_or_default();
+        widths.format(&size, &compressed_size, &compression, &modified)
+    }
+
+    fn write_children(
+        &self,
+        f: &mut Formatter<'_>,
+        tt: &mut TreeTrunk,
+        node: &Node,
+        depth: TreeDepth,
+        widths: Widths,
+    ) -> std::fmt::Result {
+        for (params, (name, child)) in depth.iterate_over(node.children.iter()) {
+            let tree_part: String = tt.new_row(params).iter().map(|x| x.ascii_art()).collect();
+            writeln!(
+                f,
+                "{}{} {}",
+                self.columns(child.entry.as_ref(), widths),
+                tree_part,
+                name
+            )?;
+            self.write_children(f, tt, child, depth.deeper(), widths)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // the totals are the widest numbers of each column
+        let widths = Widths {
+            size: self.size.to_string().len().max(1),
+            compressed_size: self.compressed_size.to_string().len().max(1),
+        };
+
+        let mut tt = TreeTrunk::default();
+        let tree_part: String = tt
+            .new_row(TreeParams::new(TreeDepth::root(), true))
+            .iter()
+            .map(|x| x.ascii_art())
+            .collect();
+        let columns = if self.long {
+            let size = self.size.to_string();
+            let compressed_size = self.compressed_size.to_string();
+            widths.format(&size, &compressed_size, "", "")
+        } else {
+            String::new()
+        };
+        writeln!(f, "{}{} {}", columns, tree_part, self.name)?;
+
+        self.write_children(f, &mut tt, &self.root, TreeDepth::root().deeper(), widths)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::listing::{ArchiveEntry, Listing};
+
+    fn entry(name: &str) -> ArchiveEntry {
+        ArchiveEntry {
+            name: name.to_string(),
+            is_dir: name.ends_with('/'),
+            size: if name.ends_with('/') { 0 } else { 100 },
+            compressed_size: if name.ends_with('/') { 0 } else { 42 },
+            compression: zip::CompressionMethod::Deflated,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn flat_entries() {
+        let listing = Listing::from_entries(
+            "a.zip".to_string(),
+            vec![entry("b.txt"), entry("a.txt")],
+            false,
+        );
+        assert_eq!(listing.to_string(), " a.zip\n├── a.txt\n└── b.txt\n");
+    }
+
+    #[test]
+    fn nested_entries() {
+        let listing = Listing::from_entries(
+            "a.zip".to_string(),
+            vec![entry("dir/"), entry("dir/a.txt"), entry("z.txt")],
+            false,
+        );
+        assert_eq!(
+            listing.to_string(),
+            " a.zip\n├── dir\n│  └── a.txt\n└── z.txt\n"
+        );
+    }
+
+    #[test]
+    fn implicit_directories() {
+        let listing =
+            Listing::from_entries("a.zip".to_string(), vec![entry("dir/sub/a.txt")], false);
+        assert_eq!(
+            listing.to_string(),
+            " a.zip\n└── dir\n   └── sub\n      └── a.txt\n"
+        );
+    }
+
+    #[test]
+    fn long_format() {
+        let listing = Listing::from_entries(
+            "a.zip".to_string(),
+            vec![entry("dir/"), entry("dir/a.txt")],
+            true,
+        );
+        let rendered = listing.to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].starts_with("100 42"));
+        assert!(lines[1].starts_with("  -  -"));
+        assert!(lines[2].starts_with("100 42 Deflated"));
+        assert!(lines[2].ends_with("└── a.txt"));
+    }
+}
@@ -1,28 +1,132 @@
 mod args;
 mod config;
+mod config_file;
 mod digest;
+mod error;
+mod explain;
+mod extract;
+mod filter;
 mod fs;
+mod listing;
+mod manifest;
+mod progress;
+mod stats;
 mod tree;
 mod tree_view;
+mod verify;
 mod wrapper;
 mod zipper;
 
+use crate::args::Command;
 use crate::config::Config;
 use crate::digest::ZipDigest;
-use crate::fs::parent_dir_name;
+use crate::error::{Error, Result};
+use crate::explain::Explainer;
+use crate::extract::Extractor;
+use crate::fs::{parent_dir_name, Snapshot};
+use crate::listing::Listing;
 use crate::tree::FileStream;
+use crate::verify::Verifier;
 use crate::zipper::Zipper;
 use clap::Parser;
+use std::process::ExitCode;
 
-fn main() {
+fn main() -> ExitCode {
     let args: args::CLI = args::CLI::parse();
-    let config = Config::from(args.clone());
 
-    FileStream::new(&config).display();
+    match run(args.command()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("lasagna: {}", err);
+            err.exit_code()
+        }
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Zip(args) => {
+            let dry_run = args.dry_run;
+            let show_ignored = args.show_ignored;
+            let config = Config::try_from(args)?;
+            let snapshot = snapshot(&config, show_ignored)?;
+
+            if !config.quiet {
+                FileStream::new(&snapshot).display();
+            }
 
-    if !args.dry_run {
-        Zipper::new(&config).build_dir().build_files().finish();
+            if !dry_run {
+                let digest = Zipper::new(&config, &snapshot)?
+                    .build_dir()?
+                    .build_files()?
+                    .build_links()?
+                    .finish()?;
+                if !config.quiet {
+                    digest.display();
+                }
+                for algorithm in &config.sidecars {
+                    let path = digest.write_sidecar(*algorithm, config.sidecar_format)?;
+                    if !config.quiet {
+                        println!("Sidecar: {}", path.display());
+                    }
+                }
+            }
+        }
+        Command::Tree(args) => {
+            let show_ignored = args.show_ignored;
+            let config = Config::try_from(args)?;
+            FileStream::new(&snapshot(&config, show_ignored)?).display();
+        }
+        Command::Verify(args) => {
+            let hash = args.hash;
+            let compare = !args.source.is_empty();
+            let checksum = args.checksum.clone();
+            let config = Config::try_from(args)?;
+
+            let mut verifier = Verifier::new(&config.output)?.check_integrity()?;
+            if compare {
+                verifier = verifier.compare_source(&config, hash)?;
+            }
+            let mut ok = verifier.finish();
+            let digest = ZipDigest::new(&config.output, &config.digests)?;
+            digest.display();
+            if let Some(checksum) = &checksum {
+                ok &= digest.check_sidecar(checksum)?;
+            }
+            if !ok {
+                return Err(Error::Verification(config.output.display().to_string()));
+            }
+        }
+        Command::List(args) => {
+            Listing::new(&args.archive, args.long)?.display();
+        }
+        Command::Extract(args) => {
+            let target = args
+                .output
+                .unwrap_or_else(|| std::path::PathBuf::from("./"));
+            Extractor::new(&args.archive, target)?
+                .keep_setuid(args.keep_setuid)
+                .extract()?
+                .finish();
+        }
+        Command::Explain(args) => {
+            let paths = args.paths.clone();
+            let config = Config::try_from(args)?;
+            let explainer = Explainer::new(&config)?;
+            for path in paths {
+                println!("{}", explainer.explain(path)?);
+            }
+        }
+    }
+    Ok(())
+}
 
-        ZipDigest::new(config.output).display();
+/// A single walk of the inputs, which both the tree and the archive are made from
+fn snapshot(config: &Config, show_ignored: bool) -> Result<Snapshot> {
+    let snapshot = Snapshot::new(config);
+    if show_ignored {
+        snapshot.with_ignored(config)
+    } else {
+        Ok(snapshot)
     }
 }
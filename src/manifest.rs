@@ -0,0 +1,137 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestFormat {
+    /// `MANIFEST.json` with the path, size, mode, mtime and SHA256 of each file
+    Json,
+    /// `SHA256SUMS`, which `sha256sum -c` can check after extraction
+    Sha256sums,
+}
+
+impl ManifestFormat {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "MANIFEST.json",
+            ManifestFormat::Sha256sums => "SHA256SUMS",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestLocation {
+    /// As an extra entry inside the archive
+    Embed,
+    /// Next to the archive, e.g. `archive.zip.MANIFEST.json`
+    Sidecar,
+}
+
+/// A file of the archive, paths are relative to the input directory
+#[derive(Serialize, Debug)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    /// Unix permissions in octal, e.g. `0644`
+    pub mode: String,
+    /// Seconds since the unix epoch
+    pub mtime: i64,
+    pub sha256: String,
+}
+
+impl ManifestEntry {
+    pub fn new(path: String, size: u64, mode: u32, mtime: i64, sha256: &[u8]) -> Self {
+        ManifestEntry {
+            path,
+            size,
+            // as stored in the archive, which never holds setuid, setgid nor sticky
+            mode: format!("{:04o}", mode & 0o777),
+            mtime,
+            sha256: base16ct::lower::encode_string(sha256),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    #[serde(skip)]
+    format: ManifestFormat,
+    files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(format: ManifestFormat) -> Self {
+        Manifest {
+            format,
+            files: vec![],
+        }
+    }
+
+    pub fn push(&mut self, entry: ManifestEntry) {
+        self.files.push(entry);
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        self.format.file_name()
+    }
+
+    pub fn render(&self) -> String {
+        match self.format {
+            ManifestFormat::Json => {
+                let mut json = serde_json::to_string_pretty(self).unwrap();
+                json.push('\n');
+                json
+            }
+            ManifestFormat::Sha256sums => {
+                let mut text = String::new();
+                for entry in &self.files {
+                    writeln!(text, "{}  {}", entry.sha256, entry.path).unwrap();
+                }
+                text
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::manifest::{Manifest, ManifestEntry, ManifestFormat};
+
+    fn manifest(format: ManifestFormat) -> Manifest {
+        let mut manifest = Manifest::new(format);
+        manifest.push(ManifestEntry::new(
+            "dir/a.txt".to_string(),
+            3,
+            0o100644,
+            1588672800,
+            &[0xab; 32],
+        ));
+        manifest
+    }
+
+    #[test]
+    fn sha256sums() {
+        let expected = format!("{}  dir/a.txt\n", "ab".repeat(32));
+        assert_eq!(manifest(ManifestFormat::Sha256sums).render(), expected);
+    }
+
+    #[test]
+    fn json() {
+        let rendered = manifest(ManifestFormat::Json).render();
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        let file = &value["files"][0];
+        assert_eq!(file["path"], "dir/a.txt");
+        assert_eq!(file["size"], 3);
+        assert_eq!(file["mode"], "0644");
+        assert_eq!(file["mtime"], 1588672800);
+        assert_eq!(file["sha256"], "ab".repeat(32));
+    }
+
+    #[test]
+    fn setuid() {
+        let entry = ManifestEntry::new("run".to_string(), 0, 0o104750, 0, &[0; 32]);
+        assert_eq!(entry.mode, "0750");
+    }
+}
@@ -0,0 +1,65 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+
+/// Files and bytes zipped so far, drawn on stdout while it is a terminal.
+///
+/// Bytes are counted once a file has been copied into the archive, so the
+/// throughput and ETA are those of the archive, not of the workers ahead of it.
+pub struct Progress {
+    bar: ProgressBar,
+    files: usize,
+    done: usize,
+}
+
+impl Progress {
+    pub fn new(files: usize, bytes: u64, quiet: bool) -> Progress {
+        let bar = if quiet || !std::io::stdout().is_terminal() {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::with_draw_target(Some(bytes), ProgressDrawTarget::stdout())
+        }
+        // gone once dropped, even when zipping is aborted
+        .with_finish(ProgressFinish::AndClear);
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{bar:30} {prefix} files, {bytes}/{total_bytes} \
+                 {binary_bytes_per_sec} ETA {eta} {wide_msg}",
+            )
+            .expect("valid progress template"),
+        );
+        // keep the rate and ETA moving while a large file is compressed
+        if !bar.is_hidden() {
+            bar.enable_steady_tick(Duration::from_millis(200));
+        }
+        let progress = Progress {
+            bar,
+            files,
+            done: 0,
+        };
+        progress.update_prefix();
+        progress
+    }
+
+    fn update_prefix(&self) {
+        self.bar.set_prefix(format!("{}/{}", self.done, self.files));
+    }
+
+    /// The file the archive is waiting for
+    pub fn current(&self, relative: &Path) {
+        self.bar.set_message(relative.display().to_string());
+    }
+
+    /// A file was copied into the archive, or skipped
+    pub fn done(&mut self, size: u64) {
+        self.done += 1;
+        self.bar.inc(size);
+        self.update_prefix();
+    }
+
+    /// Print a line, such as a warning, without garbling the bar
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.bar.suspend(f)
+    }
+}
@@ -0,0 +1,164 @@
+use indicatif::BinaryBytes;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Number of the largest entries reported
+const LARGEST: usize = 5;
+
+/// Sizes of a file before and after compression
+struct Sizes {
+    relative: PathBuf,
+    size: u64,
+    compressed: u64,
+}
+
+/// Totals of a group of files
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    size: u64,
+    compressed: u64,
+}
+
+impl Totals {
+    fn add(&mut self, sizes: &Sizes) {
+        self.files += 1;
+        self.size += sizes.size;
+        self.compressed += sizes.compressed;
+    }
+}
+
+/// `before -> after (ratio)`, the ratio being what is left of the size
+fn compression(size: u64, compressed: u64) -> String {
+    let ratio = match size {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", compressed as f64 * 100.0 / size as f64),
+    };
+    format!(
+        "{} -> {} ({})",
+        BinaryBytes(size),
+        BinaryBytes(compressed),
+        ratio
+    )
+}
+
+/// How well the files zipped compressed, printed with `--stats`
+#[derive(Default)]
+pub struct Stats {
+    files: Vec<Sizes>,
+}
+
+impl Stats {
+    pub fn push(&mut self, relative: &Path, size: u64, compressed: u64) {
+        self.files.push(Sizes {
+            relative: relative.to_path_buf(),
+            size,
+            compressed,
+        });
+    }
+
+    /// Lowercase extensions, files without one are grouped under `(none)`
+    fn by_extension(&self) -> Vec<(String, Totals)> {
+        let mut groups: HashMap<String, Totals> = HashMap::new();
+        for sizes in &self.files {
+            let extension = sizes
+                .relative
+                .extension()
+                .map(|x| x.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| "(none)".to_string());
+            groups.entry(extension).or_default().add(sizes);
+        }
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|a, b| b.1.size.cmp(&a.1.size).then_with(|| a.0.cmp(&b.0)));
+        groups
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut total = Totals::default();
+        for sizes in &self.files {
+            total.add(sizes);
+        }
+        write!(
+            f,
+            "Compressed {} file(s): {}",
+            total.files,
+            compression(total.size, total.compressed)
+        )?;
+        if self.files.is_empty() {
+            return Ok(());
+        }
+
+        let mut largest: Vec<&Sizes> = self.files.iter().collect();
+        largest.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| a.relative.cmp(&b.relative))
+        });
+        write!(f, "\nLargest:")?;
+        for sizes in largest.iter().take(LARGEST) {
+            let column = compression(sizes.size, sizes.compressed);
+            write!(f, "\n  {:<36} {}", column, sizes.relative.display())?;
+        }
+
+        let groups = self.by_extension();
+        let width = groups.iter().map(|x| x.0.len()).max().unwrap_or(0);
+        write!(f, "\nBy extension:")?;
+        for (extension, totals) in groups {
+            write!(
+                f,
+                "\n  {:<width$} {:>6} file(s)  {}",
+                extension,
+                totals.files,
+                compression(totals.size, totals.compressed),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stats::Stats;
+    use std::path::Path;
+
+    #[test]
+    fn summary() {
+        let mut stats = Stats::default();
+        stats.push(Path::new("src/main.rs"), 4096, 1024);
+        stats.push(Path::new("src/lib.RS"), 1024, 512);
+        stats.push(Path::new("image.png"), 2048, 2048);
+        stats.push(Path::new("Makefile"), 0, 0);
+        let summary = stats.to_string();
+        let lines: Vec<&str> = summary.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "Compressed 4 file(s): 7.00 KiB -> 3.50 KiB (50.0%)"
+        );
+        assert_eq!(lines[1], "Largest:");
+        assert!(lines[2].ends_with(" src/main.rs"), "{}", summary);
+        assert!(lines[2].starts_with("  4.00 KiB -> 1.00 KiB (25.0%)"));
+        assert!(lines[5].ends_with(" Makefile"), "{}", summary);
+        assert_eq!(lines[6], "By extension:");
+        assert_eq!(
+            lines[7],
+            "  rs          2 file(s)  5.00 KiB -> 1.50 KiB (30.0%)"
+        );
+        assert_eq!(
+            lines[8],
+            "  png         1 file(s)  2.00 KiB -> 2.00 KiB (100.0%)"
+        );
+        assert_eq!(lines[9], "  (none)      1 file(s)  0 B -> 0 B (-)");
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            Stats::default().to_string(),
+            "Compressed 0 file(s): 0 B -> 0 B (-)"
+        );
+    }
+}
@@ -1,15 +1,16 @@
-use crate::fs::walker;
+use crate::explain::Explanation;
+use crate::fs::{Entry, Kind, Snapshot, Special};
 use crate::tree_view::{TreeDepth, TreeParams, TreeTrunk};
-use crate::Config;
-use ignore::Walk;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::io::IsTerminal;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::vec;
 
+#[cfg(test)]
 pub fn relative_to(base: &Path, path: &Path) -> Option<usize> {
     for (i, p) in path.ancestors().enumerate() {
         if p == base {
@@ -23,6 +24,10 @@ struct FileWrapper {
     depth: usize,
     is_last: bool,
     path: PathBuf,
+    /// What left the path out, for entries shown with `--show-ignored`
+    ignored: Option<String>,
+    /// FIFOs, sockets and devices are shown, but never zipped
+    special: Option<Special>,
 }
 
 impl FileWrapper {
@@ -31,6 +36,8 @@ impl FileWrapper {
             depth,
             is_last,
             path,
+            ignored: None,
+            special: None,
         }
     }
 }
@@ -41,22 +48,79 @@ impl std::fmt::Debug for FileWrapper {
     }
 }
 
-pub struct FileStream<'a> {
+pub struct FileStream {
     items: Vec<Rc<RefCell<FileWrapper>>>,
-    config: Option<&'a Config>,
 }
 
-impl<'a> FileStream<'a> {
-    pub fn new(config: &'a Config) -> FileStream<'a> {
-        FileStream::from(walker(&config.input, Some(config)))
+impl FileStream {
+    /// The trees of all the inputs, one after the other, with the left out
+    /// entries of the snapshot if it traced them
+    pub fn new(snapshot: &Snapshot) -> FileStream {
+        let items = snapshot
+            .inputs
+            .iter()
+            .flat_map(|(_, entries)| FileStream::from(entries.as_slice()).items)
+            .collect();
+        let stream = FileStream { items };
+        if snapshot.ignored.is_empty() {
+            stream
+        } else {
+            stream.with_ignored(&snapshot.ignored)
+        }
+    }
+
+    /// Each left out entry goes in order among the rest of its directory
+    fn with_ignored(mut self, ignored: &[Explanation]) -> FileStream {
+        for explanation in ignored {
+            let Some(parent) = explanation.path.parent() else {
+                continue;
+            };
+            let Some(position) = self
+                .items
+                .iter()
+                .position(|x| x.deref().borrow().path == parent)
+            else {
+                continue;
+            };
+            let depth = self.items[position].deref().borrow().depth + 1;
+            // before the first sibling sorting after it, or at the end of the parent
+            let end = self.items[position + 1..]
+                .iter()
+                .position(|x| {
+                    let item = x.deref().borrow();
+                    item.depth < depth || (item.depth == depth && item.path > explanation.path)
+                })
+                .map_or(self.items.len(), |x| position + 1 + x);
+            let mut item = FileWrapper::new(depth, false, explanation.path.clone());
+            item.ignored = Some(explanation.rule.source());
+            self.items.insert(end, Rc::new(RefCell::new(item)));
+        }
+
+        let mut maybe_last: HashMap<PathBuf, Rc<RefCell<FileWrapper>>> = HashMap::new();
+        for item in &self.items {
+            let mut wrapper = item.deref().borrow_mut();
+            wrapper.is_last = false;
+            if let Some(parent) = wrapper.path.parent() {
+                maybe_last.insert(parent.to_path_buf(), item.clone());
+            }
+        }
+        for (_, wrapper) in maybe_last {
+            wrapper.deref().borrow_mut().is_last = true;
+        }
+        self
     }
 
+    /// Left out entries are dimmed when printing to a terminal
     pub fn display(&self) {
-        println!("{}", self);
+        if std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() {
+            println!("{:#}", self);
+        } else {
+            println!("{}", self);
+        }
     }
 }
 
-impl Display for FileStream<'_> {
+impl Display for FileStream {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut tt = TreeTrunk::default();
 
@@ -69,35 +133,45 @@ impl Display for FileStream<'_> {
             let tree_part: String = tree_part.iter().map(|x| x.ascii_art()).collect();
 
             let fw = rc.deref().borrow();
-            let prefix = fw.path.as_path().parent().unwrap();
-            let path = fw.path.strip_prefix(prefix).unwrap();
+            let path = fw.path.file_name().map(Path::new).unwrap_or(&fw.path);
+            let path = match fw.special {
+                Some(special) => format!("{} [{}]", path.display(), special),
+                None => path.display().to_string(),
+            };
 
-            writeln!(f, "{} {}", tree_part, path.display())?
+            match &fw.ignored {
+                // `{:#}` dims the entry instead of the plain suffix
+                Some(source) if f.alternate() => writeln!(
+                    f,
+                    "{} \x1b[2m{} [ignored: {}]\x1b[0m",
+                    tree_part, path, source
+                )?,
+                Some(source) => writeln!(f, "{} {} [ignored: {}]", tree_part, path, source)?,
+                None => writeln!(f, "{} {}", tree_part, path)?,
+            }
         }
         Ok(())
     }
 }
 
-impl From<ignore::Walk> for FileStream<'_> {
-    fn from(walker: Walk) -> Self {
+impl From<&[Entry]> for FileStream {
+    fn from(entries: &[Entry]) -> Self {
         let mut items: Vec<Rc<RefCell<FileWrapper>>> = vec![];
         // (Parent, Dir)
         // let mut maybe_last_dir: HashMap<PathBuf, Rc<RefCell<FileWrapper>>> = HashMap::new();
         // let mut maybe_last_file: HashMap<PathBuf, Rc<RefCell<FileWrapper>>> = HashMap::new();
         let mut maybe_last: HashMap<PathBuf, Rc<RefCell<FileWrapper>>> = HashMap::new();
 
-        for entry in walker {
-            let entry = entry.unwrap();
-            let item = Rc::new(RefCell::new(FileWrapper {
-                depth: entry.depth(),
-                is_last: false,
-                path: entry.into_path(),
-            }));
+        for entry in entries {
+            let mut wrapper = FileWrapper::new(entry.depth, false, entry.path.clone());
+            if let Kind::Special(special) = entry.kind {
+                wrapper.special = Some(special);
+            }
+            let item = Rc::new(RefCell::new(wrapper));
 
             let wrapper = item.deref().borrow();
-            let parent = wrapper.path.parent();
-            if parent.is_some() {
-                maybe_last.insert(parent.unwrap().to_path_buf(), item.clone());
+            if let Some(parent) = wrapper.path.parent() {
+                maybe_last.insert(parent.to_path_buf(), item.clone());
             }
 
             // if item.deref().borrow().path.is_dir() {
@@ -130,16 +204,16 @@ impl From<ignore::Walk> for FileStream<'_> {
             wrapper.deref().borrow_mut().is_last = true;
         }
 
-        FileStream {
-            items,
-            config: None,
-        }
+        FileStream { items }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::tree::relative_to;
+    use crate::config::Input;
+    use crate::fs::Snapshot;
+    use crate::tree::{relative_to, FileStream};
+    use crate::Config;
     use std::path::Path;
 
     #[test]
@@ -171,4 +245,34 @@ mod test {
         let path = Path::new("./rustacean/rustacean.rs");
         assert_eq!(relative_to(base, path).unwrap(), 2);
     }
+
+    #[test]
+    fn show_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join(".zipignore"), "*.log\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "").unwrap();
+        std::fs::write(root.join("src/debug.log"), "").unwrap();
+        let config = Config {
+            inputs: vec![Input::new(root)],
+            excludes: vec![".zipignore".to_string()],
+            ..Config::new().unwrap()
+        };
+
+        let stream = FileStream::new(&Snapshot::new(&config).with_ignored(&config).unwrap());
+        let tree = stream.to_string();
+        assert!(tree.contains("   └── main.rs\n"), "{}", tree);
+        assert!(
+            tree.contains("   ├── debug.log [ignored: .zipignore]\n"),
+            "{}",
+            tree
+        );
+        assert!(
+            tree.contains("├── .zipignore [ignored: --exclude]\n"),
+            "{}",
+            tree
+        );
+        assert!(format!("{:#}", stream).contains("\x1b[2mdebug.log"));
+    }
 }
@@ -142,10 +142,6 @@ impl TreeParams {
     pub fn new(depth: TreeDepth, last: bool) -> Self {
         Self { depth, last }
     }
-
-    pub fn is_at_root(&self) -> bool {
-        self.depth.0 == 0
-    }
 }
 
 impl TreeDepth {
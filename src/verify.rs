@@ -0,0 +1,332 @@
+use crate::error::{Context, Error, Result};
+use crate::fs::{FileCollector, Snapshot};
+use crate::manifest::ManifestFormat;
+use crate::Config;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// What is compared between a source file and its archive entry
+#[derive(PartialEq, Debug)]
+struct Fingerprint {
+    size: u64,
+    crc32: u32,
+    sha256: Option<[u8; 32]>,
+}
+
+impl Fingerprint {
+    fn of_file<T: AsRef<Path>>(path: T, hash: bool) -> io::Result<Fingerprint> {
+        Fingerprint::of_reader(File::open(path)?, hash)
+    }
+
+    /// A symlink entry holds the target of the link
+    fn of_link<T: AsRef<Path>>(path: T, hash: bool) -> io::Result<Fingerprint> {
+        let target = std::fs::read_link(path)?;
+        Fingerprint::of_reader(target.to_string_lossy().as_bytes(), hash)
+    }
+
+    fn of_reader<R: Read>(reader: R, hash: bool) -> io::Result<Fingerprint> {
+        let mut reader = BufReader::new(reader);
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut sha256 = Sha256::new();
+        let mut size = 0;
+        let mut buffer = [0; 1024];
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            crc32.update(&buffer[..count]);
+            if hash {
+                sha256.update(&buffer[..count]);
+            }
+            size += count as u64;
+        }
+        Ok(Fingerprint {
+            size,
+            crc32: crc32.finalize(),
+            sha256: hash.then(|| sha256.finalize().into()),
+        })
+    }
+
+    /// `None` if the entry can't be decompressed
+    fn of_entry(archive: &mut ZipArchive<File>, index: usize, hash: bool) -> Option<Fingerprint> {
+        let mut entry = archive.by_index(index).ok()?;
+        let sha256 = if hash {
+            let mut sha256 = Sha256::new();
+            io::copy(&mut entry, &mut sha256).ok()?;
+            Some(sha256.finalize().into())
+        } else {
+            None
+        };
+        Some(Fingerprint {
+            size: entry.size(),
+            crc32: entry.crc32(),
+            sha256,
+        })
+    }
+}
+
+pub struct Verifier {
+    archive: ZipArchive<File>,
+    corrupted: Vec<String>,
+    missing: Vec<String>,
+    extra: Vec<String>,
+    modified: Vec<String>,
+    /// Source paths the walk couldn't read, whose entries can't be compared
+    unreadable: Vec<String>,
+    checked: u32,
+}
+
+impl Verifier {
+    pub fn new<T: AsRef<Path>>(archive: T) -> Result<Verifier> {
+        let archive = archive.as_ref();
+        let file = File::open(archive).with_path(archive)?;
+        Ok(Verifier {
+            archive: ZipArchive::new(file).with_path(archive)?,
+            corrupted: vec![],
+            missing: vec![],
+            extra: vec![],
+            modified: vec![],
+            unreadable: vec![],
+            checked: 0,
+        })
+    }
+
+    /// Decompress every entry, the reader checks the CRC32 of each of them
+    pub fn check_integrity(mut self) -> Result<Self> {
+        for i in 0..self.archive.len() {
+            let name = self
+                .archive
+                .name_for_index(i)
+                .unwrap_or_default()
+                .to_string();
+            let intact = match self.archive.by_index(i) {
+                Ok(mut entry) => io::copy(&mut entry, &mut io::sink()).is_ok(),
+                Err(_) => false,
+            };
+            if !intact {
+                self.corrupted.push(name);
+            }
+            self.checked += 1;
+        }
+        Ok(self)
+    }
+
+    /// Walk `config.inputs` with the same rules as the zipper and compare every
+    /// file and directory against the archive. What the walk can't read fails
+    /// the verification, rather than its entries being reported as extra.
+    pub fn compare_source(mut self, config: &Config, hash: bool) -> Result<Self> {
+        let mut collector = FileCollector::new(&Snapshot::new(config), config)?;
+        let mut expected: HashSet<String> = HashSet::new();
+        // entry names under what the walk couldn't read, as directories
+        let mut unread: Vec<String> = vec![];
+        for err in collector.take_errors() {
+            let err = Error::from(err);
+            if let Error::Io { path, .. } = &err {
+                for input in &config.inputs {
+                    if let Ok(relative) = path.strip_prefix(&input.path) {
+                        unread.push(format!(
+                            "{}/",
+                            config.entry_name(&input.prefix.join(relative))?
+                        ));
+                    }
+                }
+            }
+            self.unreadable.push(err.to_string());
+        }
+
+        for (_, relative) in collector.dirs() {
+            let name = format!("{}/", config.entry_name(relative)?);
+            if self.archive.index_for_name(&name).is_none() {
+                self.missing.push(name.clone());
+            }
+            expected.insert(name);
+        }
+
+        let files = collector.files().into_iter().map(|x| (x, false));
+        let links = collector.links().into_iter().map(|x| (x, true));
+        for ((absolute, relative), is_link) in files.chain(links) {
+            let name = config.entry_name(relative)?;
+            match self.archive.index_for_name(&name) {
+                Some(index) => {
+                    let source = if is_link {
+                        Fingerprint::of_link(absolute, hash)
+                    } else {
+                        Fingerprint::of_file(absolute, hash)
+                    };
+                    // removed since the walk
+                    let source = match source {
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                            self.missing.push(name.clone());
+                            expected.insert(name);
+                            continue;
+                        }
+                        source => source.with_path(absolute)?,
+                    };
+                    let entry = Fingerprint::of_entry(&mut self.archive, index, hash);
+                    if Some(source) != entry {
+                        self.modified.push(name.clone());
+                    }
+                }
+                None => self.missing.push(name.clone()),
+            }
+            expected.insert(name);
+        }
+
+        // an embedded manifest has no counterpart in the source
+        for format in [ManifestFormat::Json, ManifestFormat::Sha256sums] {
+            expected.insert(config.entry_name(Path::new(format.file_name()))?);
+        }
+
+        self.extra = self
+            .archive
+            .file_names()
+            .filter(|name| !expected.contains(*name))
+            .filter(|name| !unread.iter().any(|x| name.starts_with(x.as_str())))
+            .map(String::from)
+            .collect();
+
+        self.missing.sort();
+        self.modified.sort();
+        self.extra.sort();
+        Ok(self)
+    }
+
+    /// Print the report, return whether the archive passed verification
+    pub fn finish(self) -> bool {
+        for name in &self.corrupted {
+            println!("CORRUPTED: {}", name);
+        }
+        for name in &self.missing {
+            println!("MISSING  : {}", name);
+        }
+        for name in &self.extra {
+            println!("EXTRA    : {}", name);
+        }
+        for name in &self.modified {
+            println!("MODIFIED : {}", name);
+        }
+        for err in &self.unreadable {
+            println!("UNREADABLE: {}", err);
+        }
+        print!(
+            "Verify Complete: {} entry(s) checked, {} corrupted, {} missing, {} extra, {} modified",
+            self.checked,
+            self.corrupted.len(),
+            self.missing.len(),
+            self.extra.len(),
+            self.modified.len()
+        );
+        if !self.unreadable.is_empty() {
+            print!(", {} unreadable", self.unreadable.len());
+        }
+        println!();
+        self.corrupted.is_empty()
+            && self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.modified.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Input;
+    use crate::fs::Snapshot;
+    use crate::manifest::ManifestFormat;
+    use crate::verify::Verifier;
+    use crate::zipper::Zipper;
+    use crate::Config;
+
+    #[test]
+    fn compare_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        std::fs::create_dir(&root).unwrap();
+        // the same size and CRC32, only the SHA256 tells them apart
+        let (before, after) = ("09685295", "12060020");
+        assert_eq!(
+            crc32fast::hash(before.as_bytes()),
+            crc32fast::hash(after.as_bytes())
+        );
+        for (name, content) in [
+            ("same.txt", "same"),
+            ("changed.txt", "before"),
+            ("gone.txt", "gone"),
+            ("collision.txt", before),
+        ] {
+            std::fs::write(root.join(name), content).unwrap();
+        }
+        let config = Config {
+            inputs: vec![Input::new(&root)],
+            output: dir.path().join("src.zip"),
+            manifest: Some(ManifestFormat::Json),
+            quiet: true,
+            ..Config::new().unwrap()
+        };
+        Zipper::new(&config, &Snapshot::new(&config))
+            .unwrap()
+            .build_dir()
+            .unwrap()
+            .build_files()
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        std::fs::write(root.join("changed.txt"), "after").unwrap();
+        std::fs::write(root.join("collision.txt"), after).unwrap();
+        std::fs::write(root.join("missing.txt"), "missing").unwrap();
+        std::fs::remove_file(root.join("gone.txt")).unwrap();
+
+        let compare = |hash| {
+            Verifier::new(&config.output)
+                .unwrap()
+                .compare_source(&config, hash)
+                .unwrap()
+        };
+        let verifier = compare(false);
+        assert_eq!(verifier.missing, ["missing.txt"]);
+        // the embedded manifest isn't extra
+        assert_eq!(verifier.extra, ["gone.txt"]);
+        assert_eq!(verifier.modified, ["changed.txt"]);
+
+        let verifier = compare(true);
+        assert_eq!(verifier.modified, ["changed.txt", "collision.txt"]);
+        assert!(!verifier.finish());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_source() {
+        use crate::fs::SymlinkMode;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        let config = Config {
+            inputs: vec![Input::new(&root)],
+            output: dir.path().join("src.zip"),
+            symlinks: SymlinkMode::Follow,
+            quiet: true,
+            ..Config::new().unwrap()
+        };
+        Zipper::new(&config, &Snapshot::new(&config))
+            .and_then(Zipper::build_files)
+            .and_then(Zipper::finish)
+            .unwrap();
+
+        // a link which can't be followed fails the walk
+        std::os::unix::fs::symlink("nowhere", root.join("broken")).unwrap();
+        let verifier = Verifier::new(&config.output)
+            .unwrap()
+            .compare_source(&config, false)
+            .unwrap();
+        assert_eq!(verifier.unreadable.len(), 1, "{:?}", verifier.unreadable);
+        assert!(verifier.unreadable[0].contains("broken"));
+        assert!(!verifier.finish());
+    }
+}
@@ -1,121 +1,602 @@
-use crate::fs::FileCollector;
-use crate::{fs, Config};
+use crate::digest::{HashingWriter, ZipDigest};
+use crate::error::{Context, Error, Result};
+use crate::fs::Stamp;
+use crate::fs::{FileCollector, Snapshot};
+use crate::manifest::{Manifest, ManifestEntry, ManifestLocation};
+use crate::progress::Progress;
+use crate::stats::Stats;
+use crate::Config;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex, PoisonError};
 
+use tempfile::{NamedTempFile, SpooledTempFile};
 use zip::write::FileOptions;
-use zip::ZipWriter;
+use zip::{ZipArchive, ZipWriter};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+/// Compressed entries larger than this are spooled to a temporary file
+const SPOOL_THRESHOLD: usize = 16 * 1024 * 1024;
+
+/// Size of the chunks files are read in
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A single entry compressed into a standalone archive kept in a spooled buffer.
+///
+/// Once raw-copied into the output, the entry header already holds the final
+/// CRC32 and sizes, so the output never has to be patched afterwards.
+struct Compressed {
+    archive: ZipArchive<SpooledTempFile>,
+    /// SHA256 of the uncompressed content, if requested
+    sha256: Option<[u8; 32]>,
+}
+
+fn compress<R: Read>(
+    reader: R,
+    name: String,
+    option: FileOptions<()>,
+    hash: bool,
+) -> zip::result::ZipResult<Compressed> {
+    let mut writer = ZipWriter::new(SpooledTempFile::new(SPOOL_THRESHOLD));
+    writer.start_file(name, option)?;
+    let mut hasher = Sha256::new();
+    // file copy
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        if hash {
+            hasher.update(&buffer[..count]);
+        }
+        writer.write_all(&buffer[..count])?;
+    }
+    Ok(Compressed {
+        archive: ZipArchive::new(writer.finish()?)?,
+        sha256: hash.then(|| hasher.finalize().into()),
+    })
+}
+
+/// A symlink entry, `add_symlink` needs to seek back to patch the header.
+/// The target is stored as is, `..` included.
+fn symlink(
+    name: String,
+    target: &str,
+    option: FileOptions<()>,
+) -> zip::result::ZipResult<Compressed> {
+    let mut writer = ZipWriter::new(SpooledTempFile::new(SPOOL_THRESHOLD));
+    writer.add_symlink(name, target, option)?;
+    Ok(Compressed {
+        archive: ZipArchive::new(writer.finish()?)?,
+        sha256: None,
+    })
+}
+
+/// A file inspected on the main thread, to be compressed by a worker
+struct Task {
+    absolute: PathBuf,
+    relative: PathBuf,
+    name: String,
+    option: FileOptions<'static, ()>,
+    size: u64,
+    stamp: Stamp,
+}
+
+impl Task {
+    fn compress(&self, hash: bool) -> Result<Compressed> {
+        let absolute = &self.absolute;
+        let file = File::open(absolute).with_path(absolute)?;
+        compress(file, self.name.clone(), self.option, hash).with_path(absolute)
+    }
+}
+
 pub struct Zipper<'a> {
     config: &'a Config,
     collector: FileCollector,
-    writer: ZipWriter<File>,
+    /// Every byte goes through the hashing writer exactly once and in order,
+    /// into a temporary file which only replaces the output once finished
+    writer: ZipWriter<HashingWriter<BufWriter<NamedTempFile>>>,
+    manifest: Option<Manifest>,
     dirs_count: u32,
     files_count: u32,
+    links_count: u32,
+    /// Sizes of the files stored, for `--stats`
+    stats: Stats,
+    /// Paths left out of the archive, as allowed by `config.on_error`,
+    /// `config.on_walk_error` and `config.on_special_file`
+    skipped: Vec<Error>,
 }
 
 impl<'a> Zipper<'a> {
-    pub fn new(config: &'a Config) -> Zipper {
+    /// The archive is written next to the output and only renamed to it by
+    /// `finish`, so that aborting leaves no archive, empty or incomplete, behind
+    pub fn new(config: &'a Config, snapshot: &Snapshot) -> Result<Zipper<'a>> {
+        let mut collector = FileCollector::new(snapshot, config)?;
+        let mut skipped = vec![];
+        for err in collector.take_errors() {
+            config
+                .on_walk_error
+                .apply(Err::<(), _>(err.into()), &mut skipped)?;
+        }
+        for (absolute, kind) in collector.special() {
+            let err = Error::input(format!(
+                "{} is a {}, which can't be zipped",
+                absolute.display(),
+                kind
+            ));
+            config
+                .on_special_file
+                .apply(Err::<(), _>(err), &mut skipped)?;
+        }
+
         let path = std::path::Path::new(&config.output);
-        let file = std::fs::File::create(path).unwrap();
-        let writer = zip::ZipWriter::new(file);
-        let collector = FileCollector::new(&config.input, Some(config));
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".lasagna");
+        // the same mode `File::create` would give, rather than 0600
+        #[cfg(unix)]
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+        let file = builder.tempfile_in(parent).with_path(path)?;
+        let algorithms = [config.digests.as_slice(), config.sidecars.as_slice()].concat();
+        let writer = HashingWriter::new(BufWriter::new(file), &algorithms);
+        let writer = zip::ZipWriter::new(writer);
 
-        Zipper {
+        Ok(Zipper {
             collector,
             writer,
+            manifest: config.manifest.map(Manifest::new),
             dirs_count: 0,
             files_count: 0,
+            links_count: 0,
+            stats: Stats::default(),
+            skipped,
             config,
+        })
+    }
+
+    fn stamp(&self, absolute: &Path, metadata: &std::fs::Metadata) -> Result<Stamp> {
+        if self.config.reproducible {
+            Stamp::reproducible(Some(metadata), self.config.source_date_epoch)
+        } else {
+            Stamp::new(metadata)
         }
+        .with_path(absolute)
     }
 
-    pub fn build_dir(mut self) -> Self {
-        let mut dirs = self.collector.dirs();
-        dirs.remove(0);
-        for (absolute, relative) in dirs {
-            let mut option: FileOptions<()> = FileOptions::default()
-                .last_modified_time(fs::last_modified(absolute))
-                .compression_method(self.config.compression_method)
-                .compression_level(self.config.compression_level);
-
-            #[cfg(unix)]
-            {
-                let permission = std::fs::File::open(absolute)
-                    .unwrap()
-                    .metadata()
-                    .unwrap()
-                    .permissions()
-                    .mode();
-                option = option.unix_permissions(permission);
-            }
+    /// Size and stamp of a path as walked, and its entry name, any of which
+    /// may fail, e.g. for a name which isn't valid UTF-8
+    fn inspect(&self, absolute: &Path, relative: &Path) -> Result<(u64, Stamp, String)> {
+        let metadata = self.collector.metadata(absolute)?;
+        let stamp = self.stamp(absolute, metadata)?;
+        let name = self.config.entry_name(relative)?;
+        Ok((metadata.len(), stamp, name))
+    }
 
-            let name = if self.config.parent {
-                format!(
-                    "{}/{}",
-                    self.config.output.file_stem().unwrap().to_str().unwrap(),
-                    relative.to_str().unwrap()
-                )
-            } else {
-                relative.to_str().unwrap().to_string()
+    pub fn build_dir(mut self) -> Result<Self> {
+        for (absolute, relative) in self.collector.dirs() {
+            let inspected = self.inspect(absolute, relative);
+            let Some((_, stamp, name)) =
+                self.config.on_error.apply(inspected, &mut self.skipped)?
+            else {
+                continue;
             };
-            self.writer.add_directory(name, option).unwrap();
+            let option: FileOptions<()> = FileOptions::default()
+                .last_modified_time(stamp.modified)
+                .compression_method(self.config.compression_method)
+                .compression_level(self.config.compression_level)
+                .unix_permissions(stamp.mode);
+
+            self.writer
+                .add_directory(name, option)
+                .with_path(&self.config.output)?;
             self.dirs_count += 1;
         }
-        self
+        Ok(self)
     }
 
-    pub fn build_files(mut self) -> Self {
+    /// Files are compressed by `config.jobs` workers, each into its own
+    /// buffer, and copied into the archive in the order they were collected,
+    /// so that the archive doesn't depend on the number of jobs
+    pub fn build_files(mut self) -> Result<Self> {
+        let mut tasks = vec![];
         for (absolute, relative) in self.collector.files() {
-            let mut option: FileOptions<()> = FileOptions::default()
-                .last_modified_time(fs::last_modified(absolute))
+            let inspected = self.inspect(absolute, relative);
+            let Some((size, stamp, name)) =
+                self.config.on_error.apply(inspected, &mut self.skipped)?
+            else {
+                continue;
+            };
+            // ZIP64 has to be decided upfront, with some headroom for
+            // incompressible data growing slightly when compressed
+            let option: FileOptions<()> = FileOptions::default()
+                .last_modified_time(stamp.modified)
                 .compression_method(self.config.compression_method)
-                .compression_level(self.config.compression_level);
-
-            #[cfg(unix)]
-            {
-                let permission = std::fs::File::open(absolute)
-                    .unwrap()
-                    .metadata()
-                    .unwrap()
-                    .permissions()
-                    .mode();
-                option = option.unix_permissions(permission);
+                .compression_level(self.config.compression_level)
+                .unix_permissions(stamp.mode)
+                .large_file(size > u32::MAX as u64 / 16 * 15);
+
+            tasks.push(Task {
+                absolute: absolute.to_path_buf(),
+                relative: relative.to_path_buf(),
+                name,
+                option,
+                size,
+                stamp,
+            });
+        }
+
+        let bytes = tasks.iter().map(|x| x.size).sum();
+        let mut progress = Progress::new(tasks.len(), bytes, self.config.quiet);
+        let hash = self.manifest.is_some();
+        let jobs = self.config.jobs.get().min(tasks.len());
+        // entries compressed ahead of the one being copied, bounding the buffers held
+        let window = jobs * 2;
+        let (task_sender, task_receiver) = mpsc::channel::<(usize, &Task)>();
+        let task_receiver = Mutex::new(task_receiver);
+        std::thread::scope(|scope| -> Result<()> {
+            // dropped on return, even early, so that idle workers stop waiting
+            let task_sender = task_sender;
+            let (done_sender, done_receiver) = mpsc::channel();
+            for _ in 0..jobs {
+                let (task_receiver, done_sender) = (&task_receiver, done_sender.clone());
+                scope.spawn(move || loop {
+                    // the lock is held while waiting for a task, not while compressing
+                    // it, and a worker which panicked leaves the receiver usable
+                    let received = task_receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    let Ok((index, task)) = received else {
+                        break;
+                    };
+                    // a panic fails the file rather than leaving the archive waiting for it
+                    let compressed = panic::catch_unwind(AssertUnwindSafe(|| task.compress(hash)))
+                        .unwrap_or_else(|_| {
+                            Err(io::Error::other("compression panicked")).with_path(&task.absolute)
+                        });
+                    // the archive is being abandoned
+                    if done_sender.send((index, compressed)).is_err() {
+                        break;
+                    }
+                });
             }
+            // only the workers hold a sender, so that waiting fails once they are all gone
+            drop(done_sender);
+
+            let mut sent = 0;
+            let mut pending = BTreeMap::new();
+            for (index, task) in tasks.iter().enumerate() {
+                while sent < tasks.len() && sent < index + window {
+                    let _ = task_sender.send((sent, &tasks[sent]));
+                    sent += 1;
+                }
+                progress.current(&task.relative);
+                let compressed = loop {
+                    if let Some(compressed) = pending.remove(&index) {
+                        break compressed;
+                    }
+                    // every worker is gone, none is left to send it
+                    let (done, compressed) = done_receiver
+                        .recv()
+                        .map_err(|_| io::Error::other("compression workers stopped early"))
+                        .with_path(&self.config.output)?;
+                    pending.insert(done, compressed);
+                };
+                self.copy_file(task, compressed, &progress)?;
+                progress.done(task.size);
+            }
+            Ok(())
+        })?;
+        Ok(self)
+    }
+
+    fn copy_file(
+        &mut self,
+        task: &Task,
+        compressed: Result<Compressed>,
+        progress: &Progress,
+    ) -> Result<()> {
+        let applied =
+            progress.suspend(|| self.config.on_error.apply(compressed, &mut self.skipped));
+        let Some(mut entry) = applied? else {
+            return Ok(());
+        };
+        let absolute = &task.absolute;
+        let file = entry.archive.by_index_raw(0).with_path(absolute)?;
+        let (size, compressed) = (file.size(), file.compressed_size());
+        self.writer
+            .raw_copy_file(file)
+            .with_path(&self.config.output)?;
+        self.stats.push(&task.relative, size, compressed);
+
+        if let (Some(manifest), Some(sha256)) = (self.manifest.as_mut(), entry.sha256) {
+            manifest.push(ManifestEntry::new(
+                task.relative.to_string_lossy().replace('\\', "/"),
+                task.size,
+                task.stamp.mode,
+                task.stamp.mtime,
+                &sha256,
+            ));
+        }
+        self.files_count += 1;
+        Ok(())
+    }
 
-            let name = if self.config.parent {
-                format!(
-                    "{}/{}",
-                    self.config.output.file_stem().unwrap().to_str().unwrap(),
-                    relative.to_str().unwrap()
-                )
-            } else {
-                relative.to_str().unwrap().to_string()
+    /// Store the links themselves, with `--symlinks preserve`
+    pub fn build_links(mut self) -> Result<Self> {
+        for (absolute, relative) in self.collector.links() {
+            let inspected = self.inspect(absolute, relative);
+            let Some((_, stamp, name)) =
+                self.config.on_error.apply(inspected, &mut self.skipped)?
+            else {
+                continue;
             };
-            self.writer.start_file(name, option).unwrap();
-            // file copy
-            let file = File::open(absolute).unwrap();
-            let mut reader = BufReader::new(file);
-            let mut buffer = [0; 1024];
-            loop {
-                let count = reader.read(&mut buffer).unwrap();
-                if count == 0 {
-                    break;
+            // the mode of a link is meaningless, rwx for everyone as `ln -s` does
+            let option: FileOptions<()> = FileOptions::default()
+                .last_modified_time(stamp.modified)
+                .unix_permissions(0o777);
+
+            let entry = std::fs::read_link(absolute)
+                .and_then(|target| {
+                    target.into_os_string().into_string().map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "link target is not valid UTF-8")
+                    })
+                })
+                .with_path(absolute)
+                .and_then(|target| symlink(name, &target, option).with_path(absolute));
+            let Some(entry) = self.config.on_error.apply(entry, &mut self.skipped)? else {
+                continue;
+            };
+            // a raw copy would turn the link into a plain file, merging keeps its mode
+            self.writer
+                .merge_archive(entry.archive)
+                .with_path(&self.config.output)?;
+            self.links_count += 1;
+        }
+        Ok(self)
+    }
+
+    /// Embed the manifest as the last entry of the archive, or write it next to it
+    fn build_manifest(&mut self) -> Result<()> {
+        let Some(manifest) = self.manifest.as_ref() else {
+            return Ok(());
+        };
+        let content = manifest.render();
+        match self.config.manifest_location {
+            ManifestLocation::Embed => {
+                let name = self.config.entry_name(Path::new(manifest.file_name()))?;
+                let mut option: FileOptions<()> = FileOptions::default()
+                    .compression_method(self.config.compression_method)
+                    .compression_level(self.config.compression_level);
+                if self.config.reproducible {
+                    let stamp = Stamp::reproducible(None, self.config.source_date_epoch)
+                        .with_path(&self.config.output)?;
+                    option = option
+                        .last_modified_time(stamp.modified)
+                        .unix_permissions(stamp.mode);
+                }
+                let output = &self.config.output;
+                let mut entry =
+                    compress(content.as_bytes(), name, option, false).with_path(output)?;
+                self.writer
+                    .raw_copy_file(entry.archive.by_index_raw(0).with_path(output)?)
+                    .with_path(output)?;
+            }
+            ManifestLocation::Sidecar => {
+                let path = self.config.sidecar(manifest.file_name());
+                std::fs::write(&path, content).with_path(&path)?;
+                if !self.config.quiet {
+                    println!("Manifest: {}", path.display());
                 }
-                self.writer.write_all(&buffer[..count]).unwrap();
             }
-            self.files_count += 1;
         }
-        self
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<ZipDigest> {
+        self.build_manifest()?;
+        let output = &self.config.output;
+        let writer = self.writer.finish().with_path(output)?;
+        let (writer, digest) = writer.finish(output).with_path(output)?;
+        let file = writer
+            .into_inner()
+            .map_err(|x| x.into_error())
+            .with_path(output)?;
+        file.persist(output)
+            .map_err(|x| x.error)
+            .with_path(output)?;
+        // what is missing from the archive is reported even when quiet
+        for err in &self.skipped {
+            eprintln!("lasagna: skipped: {}", err);
+        }
+        if !self.config.quiet {
+            print!(
+                "Zip Complete: {} directory(s), {} file(s) in total",
+                self.dirs_count, self.files_count
+            );
+            if self.links_count > 0 {
+                print!(", {} symlink(s)", self.links_count);
+            }
+            if !self.skipped.is_empty() {
+                print!(", {} path(s) skipped", self.skipped.len());
+            }
+            println!();
+            if self.config.stats {
+                println!("{}", self.stats);
+            }
+        }
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Input;
+    use crate::fs::test::LOCAL_OFFSET;
+    use crate::fs::Snapshot;
+    use crate::manifest::ManifestFormat;
+    use crate::zipper::Zipper;
+    use crate::Config;
+    use filetime::FileTime;
+    use std::num::NonZeroUsize;
+    use std::path::Path;
+    use time::UtcOffset;
+
+    fn zip(root: &Path, output: &Path, jobs: usize) -> Vec<u8> {
+        let config = Config {
+            inputs: vec![Input::new(root)],
+            output: output.to_path_buf(),
+            reproducible: true,
+            source_date_epoch: Some(1_700_000_000),
+            manifest: Some(ManifestFormat::Json),
+            jobs: NonZeroUsize::new(jobs).unwrap(),
+            quiet: true,
+            ..Config::new().unwrap()
+        };
+        Zipper::new(&config, &Snapshot::new(&config))
+            .unwrap()
+            .build_dir()
+            .unwrap()
+            .build_files()
+            .unwrap()
+            .build_links()
+            .unwrap()
+            .finish()
+            .unwrap();
+        std::fs::read(output).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reproducible() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        for i in 0..8 {
+            std::fs::write(root.join(format!("sub/{}.txt", i)), i.to_string()).unwrap();
+        }
+        std::fs::write(root.join("run.sh"), "#!/bin/sh").unwrap();
+        let checkout = |mtime: i64, mode: u32| {
+            for path in [
+                root.join("run.sh"),
+                root.join("sub/0.txt"),
+                root.join("sub"),
+            ] {
+                filetime::set_file_mtime(&path, FileTime::from_unix_time(mtime, 0)).unwrap();
+            }
+            let permissions = std::fs::Permissions::from_mode(mode);
+            std::fs::set_permissions(root.join("run.sh"), permissions).unwrap();
+        };
+
+        checkout(1_750_000_000, 0o755);
+        LOCAL_OFFSET.set(Some(UtcOffset::UTC));
+        let first = zip(&root, &dir.path().join("first.zip"), 1);
+
+        // another checkout, later, with another umask, in another timezone
+        checkout(1_800_000_000, 0o700);
+        LOCAL_OFFSET.set(Some(UtcOffset::from_hms(-7, 0, 0).unwrap()));
+        let second = zip(&root, &dir.path().join("second.zip"), 4);
+        LOCAL_OFFSET.set(None);
+
+        assert!(first == second, "the archives differ");
     }
 
-    pub fn finish(self) {
-        println!(
-            "Zip Complete: {} directory(s), {} file(s) in total",
-            self.dirs_count, self.files_count
-        );
+    #[cfg(unix)]
+    #[test]
+    fn invalid_name() {
+        use crate::error::OnError;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        std::fs::write(root.join(std::ffi::OsStr::from_bytes(b"bad\xff")), "").unwrap();
+
+        let zip = |on_error| {
+            let config = Config {
+                inputs: vec![Input::new(&root)],
+                output: dir.path().join("src.zip"),
+                on_error,
+                quiet: true,
+                ..Config::new().unwrap()
+            };
+            Zipper::new(&config, &Snapshot::new(&config))?
+                .build_files()
+                .map(|x| x.skipped.len())
+        };
+        assert!(zip(OnError::Abort).is_err());
+        assert_eq!(zip(OnError::Skip).unwrap(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn aborted() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        std::fs::create_dir_all(root.join("d")).unwrap();
+        std::fs::write(root.join(std::ffi::OsStr::from_bytes(b"bad\xff")), "").unwrap();
+        let config = Config {
+            inputs: vec![Input::new(&root)],
+            output: dir.path().join("src.zip"),
+            quiet: true,
+            ..Config::new().unwrap()
+        };
+
+        let zipped = Zipper::new(&config, &Snapshot::new(&config))
+            .and_then(Zipper::build_dir)
+            .and_then(Zipper::build_files)
+            .and_then(Zipper::build_links)
+            .and_then(Zipper::finish);
+        assert!(zipped.is_err());
+        // neither the output nor the temporary file it was written to
+        let left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|x| x.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["src"]);
+    }
+
+    #[test]
+    fn output_inside_input() {
+        use crate::digest::Algorithm;
+        use crate::manifest::ManifestLocation;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let config = Config {
+            inputs: vec![Input::new(dir.path())],
+            output: dir.path().join("./proj.zip"),
+            sidecars: vec![Algorithm::Sha256],
+            manifest: Some(ManifestFormat::Json),
+            manifest_location: ManifestLocation::Sidecar,
+            quiet: true,
+            ..Config::new().unwrap()
+        };
+
+        // the second run walks the archive, sidecars and manifest of the first
+        for _ in 0..2 {
+            let digest = Zipper::new(&config, &Snapshot::new(&config))
+                .and_then(Zipper::build_files)
+                .and_then(Zipper::finish)
+                .unwrap();
+            digest
+                .write_sidecar(Algorithm::Sha256, config.sidecar_format)
+                .unwrap();
+        }
+        let file = std::fs::File::open(&config.output).unwrap();
+        let archive = zip::ZipArchive::new(file).unwrap();
+        assert_eq!(archive.file_names().collect::<Vec<_>>(), ["a.txt"]);
     }
 }